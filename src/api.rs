@@ -1,11 +1,17 @@
 use fuzzy_filter::FuzzyFilter;
-use std::error::Error;
+use serde::Serialize;
+use std::{error::Error, future::Future};
+use tokio::sync::RwLock;
 use twitch_api2::{
     helix::{
-        channels::{ModifyChannelInformationBody, ModifyChannelInformationRequest},
+        channels::{
+            ChannelInformation, GetChannelInformationRequest, ModifyChannelInformationBody,
+            ModifyChannelInformationRequest,
+        },
         points::{
             CreateCustomRewardBody, CreateCustomRewardRequest, CustomReward,
-            GetCustomRewardRequest, UpdateCustomRewardBody, UpdateCustomRewardRequest,
+            DeleteCustomRewardRequest, GetCustomRewardRequest, UpdateCustomRewardBody,
+            UpdateCustomRewardRequest,
         },
         search::{search_categories::Category, SearchCategoriesRequest},
         streams::{
@@ -16,22 +22,167 @@ use twitch_api2::{
         },
         tags::{AutoGenerated, GetAllStreamTagsRequest, TwitchTag},
         users::{GetUsersRequest, User},
+        ClientRequestError, HelixRequestDeleteError, HelixRequestGetError, HelixRequestPatchError,
+        HelixRequestPostError, HelixRequestPutError, Request,
+    },
+    twitch_oauth2::{
+        tokens::errors::ValidationError, AccessToken, AppAccessToken, ClientId, ClientSecret,
+        RefreshToken, Scope, TwitchToken, UserToken,
     },
-    twitch_oauth2::{AccessToken, TwitchToken, UserToken},
     types::{CategoryId, Nickname, RewardId, TagId, UserId},
     HelixClient,
 };
-use twitch_oauth2::client::surf_http_client;
 
 use derivative::Derivative;
 use derive_builder::Builder;
 
-use crate::{exit, warning};
+use crate::{
+    auth::{missing_scopes, refresh_token},
+    cache::TagCache,
+    config::{AppEnv, DotEnv, TokenSource, DEFAULT_LOCALE},
+    http::TwitchHttp,
+    warning,
+};
 
+/// The errors of [`ApiClient`]
+///
+/// Errors returned by helix are mapped to a variant by their status code.
 #[derive(thiserror::Error, Debug)]
-enum ApiError {
+pub enum ApiError {
     #[error("No user with login `{0}` found.")]
     NoUser(Nickname),
+    #[error("Twitch could not find it: {0}")]
+    NotFound(String),
+    #[error("Twitch rejected the token: {0}")]
+    Unauthorized(String),
+    #[error("The token is not allowed to do this: {0}")]
+    Forbidden(String),
+    #[error("Twitch rejected the request: {0}")]
+    BadRequest(String),
+    #[error("Too many requests, Twitch's rate limit was hit. Try again later.")]
+    RateLimited,
+    #[error("Twitch failed with status {0}: {1}")]
+    Server(u16, String),
+    #[error("Could not reach Twitch: {0}")]
+    Network(String),
+    #[error("Could not get a token: {0}")]
+    Token(String),
+    #[error("Unexpected response from Twitch: {0}")]
+    Unexpected(String),
+    #[error("The token expired and can not be refreshed without a refresh token and client id.")]
+    Expired,
+    #[error(
+        "The token is missing the scopes {}.\n\
+        Run `twitchctl auth login` to get a token with all needed scopes.",
+        .0.iter().map(|s| format!("`{}`", s)).collect::<Vec<_>>().join(", ")
+    )]
+    MissingScopes(Vec<Scope>),
+    #[error(
+        "This command needs a user token and can not be run with `--app-token`.\n\
+        App access tokens can only be used to read public information."
+    )]
+    AppToken,
+    #[error("An app access token belongs to no user, specify the broadcaster.")]
+    NoBroadcaster,
+}
+
+impl ApiError {
+    /// The exit code for the category of the error
+    ///
+    /// `2` for things that were not found, `3` for missing authorization,
+    /// `4` when rate limited, `5` when Twitch could not be reached or failed
+    /// and `1` for everything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ApiError::NoUser(_) | ApiError::NotFound(_) => 2,
+            ApiError::Unauthorized(_)
+            | ApiError::Forbidden(_)
+            | ApiError::Token(_)
+            | ApiError::Expired
+            | ApiError::MissingScopes(_)
+            | ApiError::AppToken
+            | ApiError::NoBroadcaster => 3,
+            ApiError::RateLimited => 4,
+            ApiError::Server(..) | ApiError::Network(_) => 5,
+            ApiError::BadRequest(_) | ApiError::Unexpected(_) => 1,
+        }
+    }
+
+    fn from_status(status: u16, message: String) -> ApiError {
+        match status {
+            401 => ApiError::Unauthorized(message),
+            403 => ApiError::Forbidden(message),
+            404 => ApiError::NotFound(message),
+            429 => ApiError::RateLimited,
+            400..=499 => ApiError::BadRequest(message),
+            500..=599 => ApiError::Server(status, message),
+            _ => ApiError::Unexpected(format!("status {}: {}", status, message)),
+        }
+    }
+}
+
+impl<RE: Error + Send + Sync + 'static> From<ClientRequestError<RE>> for ApiError {
+    fn from(error: ClientRequestError<RE>) -> ApiError {
+        match helix_error(&error) {
+            Some((status, message)) => ApiError::from_status(status, message.to_string()),
+            None => match error {
+                ClientRequestError::RequestError(e) => ApiError::Network(e.to_string()),
+                e => ApiError::Unexpected(e.to_string()),
+            },
+        }
+    }
+}
+
+impl<RE: Error + Send + Sync + 'static> From<ValidationError<RE>> for ApiError {
+    fn from(error: ValidationError<RE>) -> ApiError {
+        match error {
+            ValidationError::NotAuthorized => ApiError::Expired,
+            ValidationError::Request(e) => ApiError::Network(e.to_string()),
+            e => ApiError::Token(e.to_string()),
+        }
+    }
+}
+
+/// The token helix requests are sent with
+#[derive(Clone)]
+enum Token {
+    User(UserToken),
+    /// a client credentials token, only valid for requests that need no user authorization
+    App(AppAccessToken),
+}
+
+impl Token {
+    fn access_token(&self) -> &AccessToken {
+        match self {
+            Token::User(token) => token.token(),
+            Token::App(token) => token.token(),
+        }
+    }
+
+    fn client_id(&self) -> &ClientId {
+        match self {
+            Token::User(token) => token.client_id(),
+            Token::App(token) => token.client_id(),
+        }
+    }
+}
+
+/// Sends a helix request with the token of the client, see [`ApiClient::send`]
+///
+/// `helix!(client, req_put(req, body))` calls `req_put` of the helix client with
+/// clones of `req` and `body` and the current token.
+macro_rules! helix {
+    ($client:expr, $method:ident($($arg:ident),+)) => {
+        $client.send(|token| {
+            $(let $arg = $arg.clone();)+
+            async move {
+                match &token {
+                    Token::User(token) => $client.helix_client.$method($($arg),+, token).await,
+                    Token::App(token) => $client.helix_client.$method($($arg),+, token).await,
+                }
+            }
+        })
+    };
 }
 
 pub enum UserIdent {
@@ -40,51 +191,294 @@ pub enum UserIdent {
     None,
 }
 
-async fn get_user(token_string: &str) -> Result<UserToken, Box<dyn Error + 'static>> {
+async fn get_user(http: &TwitchHttp, env: &DotEnv) -> Result<UserToken, ApiError> {
     let token = UserToken::from_existing(
-        surf_http_client,
-        AccessToken::new(token_string.to_string()),
-        None,
-        None,
+        |req| http.oauth(req),
+        AccessToken::new(env.token.clone()),
+        env.refresh_token.clone().map(RefreshToken::new),
+        env.client_secret.clone().map(ClientSecret::new),
     )
-    .await?;
-    token.validate_token(surf_http_client).await?;
+    .await;
 
-    Ok(token)
+    match (token, &env.client_id, &env.refresh_token) {
+        (Err(ValidationError::NotAuthorized), Some(client_id), Some(refresh)) => refresh_token(
+            http,
+            &env.profile,
+            &env.source,
+            client_id,
+            refresh,
+            env.client_secret.as_deref(),
+            env.store_client_secret,
+        )
+        .await
+        .map_err(|e| ApiError::Token(e.to_string())),
+        (Err(ValidationError::NotAuthorized), _, _) => Err(ApiError::Expired),
+        (token, _, _) => Ok(token?),
+    }
+}
+
+async fn get_app_token(
+    http: &TwitchHttp,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<AppAccessToken, ApiError> {
+    AppAccessToken::get_app_access_token(
+        |req| http.oauth(req),
+        ClientId::new(client_id.to_string()),
+        ClientSecret::new(client_secret.to_string()),
+        vec![],
+    )
+    .await
+    .map_err(|e| ApiError::Token(e.to_string()))
+}
+
+/// The status code and message of an error returned by helix
+fn helix_error<RE: Error + Send + Sync + 'static>(
+    error: &ClientRequestError<RE>,
+) -> Option<(u16, &str)> {
+    match error {
+        ClientRequestError::HelixRequestGetError(HelixRequestGetError::Error {
+            status,
+            message,
+            ..
+        })
+        | ClientRequestError::HelixRequestPutError(HelixRequestPutError::Error {
+            status,
+            message,
+            ..
+        })
+        | ClientRequestError::HelixRequestPostError(HelixRequestPostError::Error {
+            status,
+            message,
+            ..
+        })
+        | ClientRequestError::HelixRequestPatchError(HelixRequestPatchError::Error {
+            status,
+            message,
+            ..
+        })
+        | ClientRequestError::HelixRequestDeleteError(HelixRequestDeleteError::Error {
+            status,
+            message,
+            ..
+        }) => Some((status.as_u16(), message)),
+        _ => None,
+    }
 }
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct ApiClient<'a> {
     #[derivative(Debug = "ignore")]
-    helix_client: HelixClient<'a, surf::Client>,
-    token: UserToken,
-    user: UserId,
+    http: TwitchHttp,
+    #[derivative(Debug = "ignore")]
+    helix_client: HelixClient<'a, TwitchHttp>,
+    #[derivative(Debug = "ignore")]
+    token: RwLock<Token>,
+    #[derivative(Debug = "ignore")]
+    client_secret: Option<String>,
+    /// whether the client secret is stored with rotated tokens
+    store_client_secret: bool,
+    profile: String,
+    #[derivative(Debug = "ignore")]
+    source: TokenSource,
+    locale: Option<String>,
+    broadcaster: Option<Nickname>,
+    scopes: Vec<Scope>,
+    /// `None` for app access tokens
+    login: Option<Nickname>,
+    user: Option<UserId>,
+    tag_cache: Option<TagCache>,
+    /// log the mutating requests instead of sending them
+    dry_run: bool,
 }
 
 impl<'a> ApiClient<'a> {
-    pub async fn new(token: &str) -> Result<ApiClient<'a>, Box<dyn Error>> {
-        let token = get_user(token).await?;
+    pub async fn new(env: &DotEnv) -> Result<ApiClient<'a>, ApiError> {
+        ApiClient::with_http(env, TwitchHttp::surf(env.endpoints.clone())).await
+    }
+
+    /// Creates a client sending its requests with `http`, e.g. to a fake Twitch
+    pub async fn with_http(env: &DotEnv, http: TwitchHttp) -> Result<ApiClient<'a>, ApiError> {
+        let token = get_user(&http, env).await?;
+        Ok(ApiClient {
+            helix_client: HelixClient::with_client(http.clone()),
+            http,
+            scopes: token.scopes().to_vec(),
+            login: Some(token.login.clone().into()),
+            user: Some(token.user_id.clone().into()),
+            token: RwLock::new(Token::User(token)),
+            client_secret: env.client_secret.clone(),
+            store_client_secret: env.store_client_secret,
+            profile: env.profile.clone(),
+            source: env.source.clone(),
+            locale: env.locale.clone(),
+            broadcaster: env.broadcaster.clone().map(Into::into),
+            tag_cache: None,
+            dry_run: false,
+        })
+    }
+
+    /// Creates a client with an app access token from the client credentials
+    ///
+    /// The client can only be used for requests that need no user authorization.
+    pub async fn with_app_token(env: &AppEnv) -> Result<ApiClient<'a>, ApiError> {
+        ApiClient::with_app_http(env, TwitchHttp::surf(env.endpoints.clone())).await
+    }
+
+    /// Creates a client with an app access token sending its requests with `http`
+    pub async fn with_app_http(env: &AppEnv, http: TwitchHttp) -> Result<ApiClient<'a>, ApiError> {
+        let token = get_app_token(&http, &env.client_id, &env.client_secret).await?;
         Ok(ApiClient {
-            helix_client: HelixClient::with_client(surf::Client::new()),
-            token: token.clone(),
-            user: token.user_id.into(),
+            helix_client: HelixClient::with_client(http.clone()),
+            http,
+            scopes: vec![],
+            login: None,
+            user: None,
+            token: RwLock::new(Token::App(token)),
+            client_secret: Some(env.client_secret.clone()),
+            store_client_secret: false,
+            profile: env.profile.clone(),
+            // app access tokens are requested again instead of being stored
+            source: TokenSource::Env,
+            locale: env.locale.clone(),
+            broadcaster: env.broadcaster.clone().map(Into::into),
+            tag_cache: None,
+            dry_run: false,
         })
     }
 
-    pub fn get_user(&self) -> &str {
-        self.token.login.as_ref()
+    /// Uses `cache` for the tag catalogue, see [`TagCache`]
+    ///
+    /// The cache is not used with a cassette, it has to hold the requests for the tags.
+    pub fn with_tag_cache(mut self, cache: Option<TagCache>) -> ApiClient<'a> {
+        self.tag_cache = cache.filter(|_| !self.http.is_cassette());
+        self
     }
 
-    pub fn get_user_id(&self) -> &UserId {
-        &self.user
+    /// With `dry_run`, requests changing the channel are logged instead of sent
+    pub fn with_dry_run(mut self, dry_run: bool) -> ApiClient<'a> {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Logs the method, endpoint and body of a mutating request in dry-run mode
+    ///
+    /// Returns whether the request was logged, it must not be sent then.
+    fn dry_run<R: Request, B: Serialize>(&self, method: &str, request: &R, body: &B) -> bool {
+        if self.dry_run {
+            println!(
+                "[DRY RUN] {} {}/{}?{} {}",
+                method,
+                self.http.endpoints().helix_url,
+                R::PATH,
+                request.query().unwrap_or_default(),
+                serde_json::to_string(body).unwrap_or_default()
+            );
+        }
+        self.dry_run
+    }
+
+    /// Sends a request with the current token
+    ///
+    /// If helix rejects the token, it is refreshed and the request is retried once.
+    async fn send<T, R, F, RE>(&self, request: R) -> Result<T, ApiError>
+    where
+        R: Fn(Token) -> F,
+        F: Future<Output = Result<T, ClientRequestError<RE>>>,
+        RE: Error + Send + Sync + 'static,
+    {
+        let token = self.token.read().await.clone();
+        let refreshable = match &token {
+            Token::User(token) => token.refresh_token.is_some(),
+            Token::App(_) => self.client_secret.is_some(),
+        };
+        match request(token.clone()).await.map_err(ApiError::from) {
+            Err(ApiError::Unauthorized(_)) if refreshable => {
+                let token = self.refresh(&token).await?;
+                Ok(request(token).await?)
+            }
+            res => res,
+        }
+    }
+
+    async fn refresh(&self, expired: &Token) -> Result<Token, ApiError> {
+        let mut token = self.token.write().await;
+        // another request might have refreshed the token already
+        if token.access_token().secret() == expired.access_token().secret() {
+            let client_id = token.client_id().as_str().to_string();
+            *token = match &*token {
+                Token::User(user) => {
+                    let refresh = user
+                        .refresh_token
+                        .as_ref()
+                        .ok_or(ApiError::Expired)?
+                        .secret()
+                        .clone();
+                    Token::User(
+                        refresh_token(
+                            &self.http,
+                            &self.profile,
+                            &self.source,
+                            &client_id,
+                            &refresh,
+                            self.client_secret.as_deref(),
+                            self.store_client_secret,
+                        )
+                        .await
+                        .map_err(|e| ApiError::Token(e.to_string()))?,
+                    )
+                }
+                Token::App(_) => Token::App(
+                    get_app_token(
+                        &self.http,
+                        &client_id,
+                        self.client_secret.as_deref().ok_or(ApiError::Expired)?,
+                    )
+                    .await?,
+                ),
+            };
+        }
+        Ok(token.clone())
+    }
+
+    pub fn get_user(&self) -> Result<&Nickname, ApiError> {
+        self.login.as_ref().ok_or(ApiError::AppToken)
+    }
+
+    /// The id of the token's user, fails for app access tokens
+    pub fn get_user_id(&self) -> Result<&UserId, ApiError> {
+        self.user.as_ref().ok_or(ApiError::AppToken)
+    }
+
+    /// Fails with the missing scopes if the token was not granted all of `needed`
+    ///
+    /// App access tokens have no scopes, commands needing any are refused.
+    pub fn check_scopes(&self, needed: &[Scope]) -> Result<(), ApiError> {
+        let missing = missing_scopes(&self.scopes, needed);
+        if !missing.is_empty() && self.user.is_none() {
+            Err(ApiError::AppToken)
+        } else if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::MissingScopes(missing))
+        }
+    }
+
+    /// The locale for tag names of the profile, `en-us` if it has none
+    pub fn locale(&self) -> &str {
+        self.locale.as_deref().unwrap_or(DEFAULT_LOCALE)
     }
 
     pub async fn search_categories(
         &self,
         term: &str,
         max: usize,
-    ) -> Result<Option<Vec<Category>>, Box<dyn Error>> {
+    ) -> Result<Option<Vec<Category>>, ApiError> {
         // TODO Implement some better filter (only starting with for example) to reduce the number
         // of results for searches
 
@@ -92,14 +486,14 @@ impl<'a> ApiClient<'a> {
             .query(term)
             .first(max.max(1).min(100).to_string())
             .build();
-        let res: Vec<Category> = self.helix_client.req_get(req, &self.token).await?.data;
-        if res.len() > 0 {
+        let res: Vec<Category> = helix!(self, req_get(req)).await?.data;
+        if !res.is_empty() {
             Ok(Some(res))
         } else {
             Ok(None)
         }
     }
-    pub async fn search_category(&self, term: &str) -> Result<Option<Category>, Box<dyn Error>> {
+    pub async fn search_category(&self, term: &str) -> Result<Option<Category>, ApiError> {
         match self.search_categories(term, 1).await? {
             Some(cs) => Ok(Some(cs[0].clone())),
             None => Ok(None),
@@ -110,7 +504,7 @@ impl<'a> ApiClient<'a> {
         &self,
         user_names: &[&Nickname],
         user_ids: &[&UserId],
-    ) -> Result<Vec<User>, Box<dyn Error>> {
+    ) -> Result<Vec<User>, ApiError> {
         let user_names: Vec<Nickname> = user_names.iter().cloned().cloned().collect();
         let user_ids: Vec<UserId> = user_ids.iter().cloned().cloned().collect();
         let req = match (user_names.len(), user_ids.len()) {
@@ -123,7 +517,7 @@ impl<'a> ApiClient<'a> {
                 .build(),
         };
 
-        let res: Vec<User> = self.helix_client.req_get(req, &self.token).await?.data;
+        let res: Vec<User> = helix!(self, req_get(req)).await?.data;
         Ok(res)
     }
 
@@ -131,24 +525,55 @@ impl<'a> ApiClient<'a> {
         &self,
         broadcaster_id: &UserId,
         tag_ids: Vec<TagId>,
-    ) -> Result<ReplaceStreamTags, Box<dyn Error + 'static>> {
+    ) -> Result<ReplaceStreamTags, ApiError> {
         let req = ReplaceStreamTagsRequest::builder()
             .broadcaster_id(broadcaster_id.clone())
             .build();
         let body = ReplaceStreamTagsBody::builder().tag_ids(tag_ids).build();
-        let res = self.helix_client.req_put(req, body, &self.token).await?;
+        if self.dry_run("PUT", &req, &body) {
+            return Ok(ReplaceStreamTags::Success);
+        }
+        let res = helix!(self, req_put(req, body)).await?;
         Ok(res.data)
     }
 
-    pub async fn get_stream_tags(&self, id: &UserId) -> Result<Vec<TwitchTag>, Box<dyn Error>> {
+    pub async fn get_stream_tags(&self, id: &UserId) -> Result<Vec<TwitchTag>, ApiError> {
         let tag_req = GetStreamTagsRequest::builder()
             .broadcaster_id(id.clone())
             .build();
-        let tag_res = self.helix_client.req_get(tag_req, &self.token).await?;
+        let tag_res = helix!(self, req_get(tag_req)).await?;
         Ok(tag_res.data)
     }
 
-    pub async fn get_all_tags(&self) -> Result<Vec<TwitchTag>, Box<dyn Error>> {
+    /// All tags, from the tag cache if it is fresh or Twitch can't be reached
+    pub async fn get_all_tags(&self) -> Result<Vec<TwitchTag>, ApiError> {
+        let helix_url = &self.http.endpoints().helix_url;
+        let cache = match &self.tag_cache {
+            Some(cache) => cache,
+            None => return self.fetch_all_tags().await,
+        };
+        if let Some(tags) = cache.fresh(helix_url) {
+            return Ok(tags);
+        }
+        match self.fetch_all_tags().await {
+            Ok(tags) => {
+                if let Err(e) = cache.store(helix_url, &tags) {
+                    warning!("Could not cache the tags: {}", e);
+                }
+                Ok(tags)
+            }
+            Err(ApiError::Network(e)) => match cache.load(helix_url) {
+                Some((_, tags)) => {
+                    warning!("Could not reach Twitch ({}), using the cached tags.", e);
+                    Ok(tags)
+                }
+                None => Err(ApiError::Network(e)),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn fetch_all_tags(&self) -> Result<Vec<TwitchTag>, ApiError> {
         let mut tags = vec![];
         let mut pagination = None;
         loop {
@@ -156,7 +581,7 @@ impl<'a> ApiClient<'a> {
                 .after(pagination)
                 .first(Some(100))
                 .build();
-            let mut res = self.helix_client.req_get(req, &self.token).await?;
+            let mut res = helix!(self, req_get(req)).await?;
             tags.append(&mut res.data);
             pagination = res.pagination;
             if pagination == None {
@@ -170,7 +595,21 @@ impl<'a> ApiClient<'a> {
         &self,
         tags: &[String],
         locale: &str,
-    ) -> Result<Vec<TagId>, Box<dyn Error>> {
+    ) -> Result<Vec<TagId>, ApiError> {
+        Ok(self
+            .get_tags_matching(tags, locale)
+            .await?
+            .into_iter()
+            .map(|tag| tag.id)
+            .collect())
+    }
+
+    /// The manual tags named like `tags` in `locale`, or in English if they have no localized name
+    pub async fn get_tags_matching(
+        &self,
+        tags: &[String],
+        locale: &str,
+    ) -> Result<Vec<TwitchTag>, ApiError> {
         let all_tags = self.get_all_tags().await?;
 
         Ok(tags
@@ -185,7 +624,7 @@ impl<'a> ApiClient<'a> {
                             if loc_name.eq_ignore_ascii_case(tag)
                                 && tag_obj.is_auto == AutoGenerated::False =>
                         {
-                            return Some(tag_obj.id.clone())
+                            return Some(tag_obj.clone())
                         }
                         (None, Some(en_name))
                             if en_name.eq_ignore_ascii_case(tag)
@@ -197,7 +636,7 @@ impl<'a> ApiClient<'a> {
                                 en_name,
                                 locale
                             );
-                            return Some(tag_obj.id.clone());
+                            return Some(tag_obj.clone());
                         }
                         _ => {}
                     }
@@ -209,15 +648,23 @@ impl<'a> ApiClient<'a> {
     pub async fn get_broadcaster_id(
         &self,
         broadcaster_ident: UserIdent,
-    ) -> Result<UserId, Box<dyn Error>> {
+    ) -> Result<UserId, ApiError> {
+        let broadcaster_ident = match (broadcaster_ident, &self.broadcaster) {
+            // fall back to the broadcaster of the profile
+            (UserIdent::None, Some(broadcaster)) => UserIdent::UserName(broadcaster.clone()),
+            (ident, _) => ident,
+        };
         match broadcaster_ident {
-            UserIdent::None => Ok(self.get_user_id().clone()),
+            UserIdent::None => match &self.user {
+                Some(user) => Ok(user.clone()),
+                None => Err(ApiError::NoBroadcaster),
+            },
             UserIdent::UserId(broadcaster_id) => Ok(broadcaster_id),
             UserIdent::UserName(broadcaster_name) => {
                 match self.get_users(&[&broadcaster_name], &[]).await {
                     Ok(userlist) => {
                         if userlist.is_empty() {
-                            Err(Box::new(ApiError::NoUser(broadcaster_name)))
+                            Err(ApiError::NoUser(broadcaster_name))
                         } else {
                             Ok(userlist[0].id.clone())
                         }
@@ -227,31 +674,48 @@ impl<'a> ApiClient<'a> {
             }
         }
     }
+    pub async fn get_channel_information(
+        &self,
+        id: &UserId,
+    ) -> Result<Option<ChannelInformation>, ApiError> {
+        let req = GetChannelInformationRequest::builder()
+            .broadcaster_id(id.clone())
+            .build();
+        let res = helix!(self, req_get(req)).await?;
+        Ok(res.data)
+    }
+
     pub async fn modify_channel_information(
         &self,
         id: &UserId,
         info: ChannelInfo,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), ApiError> {
         let req = ModifyChannelInformationRequest::builder()
             .broadcaster_id(id.clone())
             .build();
 
         let body = info.to_modify_body();
-        self.helix_client.req_patch(req, body, &self.token).await?;
+        if self.dry_run("PATCH", &req, &body) {
+            return Ok(());
+        }
+        helix!(self, req_patch(req, body)).await?;
         Ok(())
     }
 
+    /// Creates the reward and returns it, `None` in dry-run mode
     pub async fn create_custom_reward(
         &self,
         id: &UserId,
         reward: CreateCustomRewardBody,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<Option<CustomReward>, ApiError> {
         let req = CreateCustomRewardRequest::builder()
             .broadcaster_id(id.clone())
             .build();
-
-        self.helix_client.req_post(req, reward, &self.token).await?;
-        Ok(())
+        if self.dry_run("POST", &req, &reward) {
+            return Ok(None);
+        }
+        let res = helix!(self, req_post(req, reward)).await?;
+        Ok(Some(res.data))
     }
 
     pub async fn update_custom_reward(
@@ -259,22 +723,39 @@ impl<'a> ApiClient<'a> {
         broadcaster_id: &UserId,
         reward_id: &RewardId,
         reward: UpdateCustomRewardBody,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), ApiError> {
         let req = UpdateCustomRewardRequest::builder()
             .broadcaster_id(broadcaster_id.clone())
             .id(reward_id.clone())
             .build();
-        self.helix_client
-            .req_patch(req, reward, &self.token)
-            .await?;
+        if self.dry_run("PATCH", &req, &reward) {
+            return Ok(());
+        }
+        helix!(self, req_patch(req, reward)).await?;
+        Ok(())
+    }
+
+    pub async fn delete_custom_reward(
+        &self,
+        broadcaster_id: &UserId,
+        reward_id: &RewardId,
+    ) -> Result<(), ApiError> {
+        let req = DeleteCustomRewardRequest::builder()
+            .broadcaster_id(broadcaster_id.clone())
+            .id(reward_id.clone())
+            .build();
+        if self.dry_run("DELETE", &req, &()) {
+            return Ok(());
+        }
+        helix!(self, req_delete(req)).await?;
         Ok(())
     }
 
-    pub async fn get_rewards(&self, id: &UserId) -> Result<Vec<CustomReward>, Box<dyn Error>> {
+    pub async fn get_rewards(&self, id: &UserId) -> Result<Vec<CustomReward>, ApiError> {
         let tag_req = GetCustomRewardRequest::builder()
             .broadcaster_id(id.clone())
             .build();
-        let tag_res = self.helix_client.req_get(tag_req, &self.token).await?;
+        let tag_res = helix!(self, req_get(tag_req)).await?;
         Ok(tag_res.data)
     }
 
@@ -282,31 +763,35 @@ impl<'a> ApiClient<'a> {
         &self,
         id: &UserId,
         query: &str,
-    ) -> Result<Option<CustomReward>, Box<dyn Error>> {
+    ) -> Result<Option<CustomReward>, ApiError> {
         let rewards = self.get_rewards(id).await?;
+        Ok(match_reward(&rewards, query).cloned())
+    }
+}
 
-        if let Some(reward) = rewards.iter().find(|r| r.title == query) {
-            Ok(Some(reward.clone()))
+/// The reward titled `query`, ignoring case, or else the only one matching it fuzzily
+pub fn match_reward<'r>(rewards: &'r [CustomReward], query: &str) -> Option<&'r CustomReward> {
+    if let Some(reward) = rewards.iter().find(|r| r.title == query) {
+        Some(reward)
+    } else {
+        let query = query.to_lowercase();
+        let rewards_ic: Vec<_> = rewards
+            .iter()
+            .filter(|r| r.title.to_lowercase() == query)
+            .collect();
+        if rewards_ic.len() == 1 {
+            Some(rewards_ic[0])
         } else {
-            let query = query.to_lowercase();
-            let rewards_ic: Vec<_> = rewards
+            let query = FuzzyFilter::new(&query);
+            let mut rewards = rewards
                 .iter()
-                .filter(|r| r.title.to_lowercase() == query)
-                .collect();
-            if rewards_ic.len() == 1 {
-                Ok(Some((rewards_ic[0]).clone()))
+                .filter(|CustomReward { title, .. }| query.matches(&title.to_lowercase()));
+            let reward = rewards.next();
+
+            if reward.is_some() && rewards.next().is_none() {
+                reward
             } else {
-                let query = FuzzyFilter::new(&query);
-                let mut rewards = rewards
-                    .iter()
-                    .filter(|CustomReward { title, .. }| query.matches(&title.to_lowercase()));
-                let reward = rewards.next();
-
-                if reward.is_some() && !rewards.next().is_some() {
-                    Ok(Some(reward.unwrap().clone()))
-                } else {
-                    Ok(None)
-                }
+                None
             }
         }
     }
@@ -329,20 +814,16 @@ impl ChannelInfo {
     }
 }
 
-pub async fn get_broadcaster_id_or_die(
+/// The id of the given broadcaster, falling back to the profile's broadcaster or the token's user
+pub async fn resolve_broadcaster_id(
     client: &ApiClient<'_>,
     broadcaster: Option<Nickname>,
     broadcaster_id: Option<UserId>,
-) -> UserId {
-    let broadcaster_id = match (broadcaster, broadcaster_id) {
-        (_, Some(i)) => client.get_broadcaster_id(UserIdent::UserId(i.into())),
-        (Some(b), _) => client.get_broadcaster_id(UserIdent::UserName(b.into())),
+) -> Result<UserId, ApiError> {
+    match (broadcaster, broadcaster_id) {
+        (_, Some(i)) => client.get_broadcaster_id(UserIdent::UserId(i)),
+        (Some(b), _) => client.get_broadcaster_id(UserIdent::UserName(b)),
         _ => client.get_broadcaster_id(UserIdent::None),
     }
-    .await;
-
-    match broadcaster_id {
-        Ok(id) => id,
-        Err(e) => exit!(1, "{}", e),
-    }
+    .await
 }
@@ -0,0 +1,627 @@
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, net::Ipv4Addr, time::Duration};
+use structopt::StructOpt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use twitch_api2::twitch_oauth2::{
+    oauth2::url::Url, revoke_token, tokens::errors::ValidationError, validate_token, AccessToken,
+    ClientId, ClientSecret, RefreshToken, Scope, UserToken, ValidatedToken,
+};
+
+use crate::config::{
+    self, load_endpoints, load_env, load_profile, remove_token, save_token, TokenSource,
+    DEFAULT_PROFILE,
+};
+use crate::credentials::passphrase;
+use crate::http::TwitchHttp;
+
+/// The scopes needed by all of twitchctl's commands
+pub const SCOPES: &[Scope] = &[
+    Scope::ChannelManageBroadcast,
+    Scope::ChannelManageRedemptions,
+];
+
+/// Scopes that grant the permissions of other scopes
+const IMPLIED_SCOPES: &[(Scope, Scope)] = &[
+    (
+        Scope::ChannelManageRedemptions,
+        Scope::ChannelReadRedemptions,
+    ),
+    (Scope::UserEditBroadcast, Scope::ChannelManageBroadcast),
+];
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// How long to wait for the redirect of `auth login --browser`
+const BROWSER_LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, StructOpt)]
+/// log in to twitch and manage the stored token
+pub struct AuthOptions {
+    #[structopt(subcommand)]
+    pub subcommand: AuthSubcommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AuthSubcommand {
+    /// log in to twitch and store the token for further use
+    ///
+    /// Uses the OAuth device code flow: open the printed url on any device
+    /// and enter the code to authorize twitchctl.
+    ///
+    /// With `--browser` the authorization code flow is used instead, which
+    /// needs the client secret of a confidential application and
+    /// `http://127.0.0.1:<port>` as one of its OAuth redirect URLs.
+    Login {
+        /// the client id of your twitch application
+        #[structopt(long, env = "TWITCHCTL_CLIENT_ID")]
+        client_id: String,
+        /// the client secret of your twitch application, needed for `--browser`
+        ///
+        /// Prefer setting it in the env, arguments show up in `ps` and the shell
+        /// history. A secret given as argument is stored with the token, one from
+        /// the env is not.
+        #[structopt(long, env = "TWITCHCTL_CLIENT_SECRET", hide_env_values = true)]
+        client_secret: Option<String>,
+        /// authorize in the browser and receive the redirect on a local port
+        #[structopt(long)]
+        browser: bool,
+        /// the port to receive the redirect of `--browser` on
+        #[structopt(long, default_value = "17563")]
+        port: u16,
+        /// store the token encrypted with a passphrase instead of in `profiles.toml`
+        ///
+        /// The passphrase is read from `TWITCHCTL_PASSPHRASE` or the terminal.
+        #[structopt(long)]
+        encrypt: bool,
+    },
+    /// show the user, expiry and scopes of the token
+    Status {
+        /// print the status as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// list the scopes of the token and the scopes it is missing
+    Scopes {
+        /// print the scopes as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// revoke the token and remove it from the profile
+    Revoke,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Twitch returned an error: {0}")]
+    Twitch(String),
+    #[error("The device code expired before the login was authorized.")]
+    Expired,
+    #[error("The login was denied.")]
+    Denied,
+    #[error("The token of profile `{0}` is expired or was revoked.")]
+    Invalid(String),
+    #[error(
+        "`--browser` needs the client secret, set `--client-secret` or TWITCHCTL_CLIENT_SECRET."
+    )]
+    NoClientSecret,
+    #[error("The redirect did not contain the expected state, the login was aborted.")]
+    StateMismatch,
+    #[error("Timed out waiting for the login to be authorized.")]
+    Timeout,
+}
+
+/// The authorization code flow of `auth login --browser`
+pub struct Browser<'a> {
+    /// the port receiving the redirect, `0` picks a free one
+    pub port: u16,
+    pub client_secret: &'a str,
+    /// whether the secret is stored with the token
+    pub store_secret: bool,
+    /// called with the url to authorize twitchctl at
+    pub open: Box<dyn FnOnce(&Url) + 'a>,
+}
+
+/// What `auth status` and `auth scopes` show about the token of a profile
+#[derive(Serialize, Debug)]
+pub struct TokenStatus {
+    pub profile: String,
+    pub login: Option<String>,
+    pub user_id: Option<String>,
+    pub client_id: String,
+    /// seconds until the token expires, `None` if it never expires
+    pub expires_in: Option<u64>,
+    pub scopes: Vec<String>,
+    /// the scopes of [`SCOPES`] the token is missing
+    pub missing_scopes: Vec<String>,
+}
+
+impl TokenStatus {
+    fn new(profile: &str, token: ValidatedToken) -> TokenStatus {
+        let scopes = token.scopes.unwrap_or_default();
+        TokenStatus {
+            profile: profile.to_string(),
+            login: token.login,
+            user_id: token.user_id,
+            client_id: token.client_id.as_str().to_string(),
+            expires_in: Some(token.expires_in.as_secs()).filter(|&secs| secs != 0),
+            missing_scopes: missing_scopes(&scopes, SCOPES)
+                .iter()
+                .map(Scope::to_string)
+                .collect(),
+            scopes: scopes.iter().map(Scope::to_string).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DeviceCode {
+    device_code: String,
+    expires_in: u64,
+    interval: u64,
+    user_code: String,
+    verification_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    message: String,
+}
+
+pub async fn auth(profile: Option<&str>, command: AuthSubcommand) -> Result<(), Box<dyn Error>> {
+    let http = TwitchHttp::surf(load_endpoints(profile)?);
+    match command {
+        AuthSubcommand::Login {
+            client_id,
+            client_secret,
+            browser,
+            port,
+            encrypt,
+        } => {
+            // the secret is not used by the device flow
+            let client_secret = match (browser, client_secret) {
+                (true, Some(secret)) => {
+                    let from_env = config::client_secret().as_ref() == Some(&secret);
+                    Some((secret, !from_env))
+                }
+                (true, None) => return Err(AuthError::NoClientSecret.into()),
+                (false, _) => None,
+            };
+            let browser = client_secret
+                .as_ref()
+                .map(|(client_secret, store_secret)| Browser {
+                    port,
+                    client_secret,
+                    store_secret: *store_secret,
+                    open: Box::new(|url| {
+                        println!("Open {} in your browser to authorize twitchctl.", url)
+                    }),
+                });
+            login(
+                &http,
+                profile.unwrap_or(DEFAULT_PROFILE),
+                &client_id,
+                browser,
+                encrypt,
+            )
+            .await
+        }
+        AuthSubcommand::Status { json } => {
+            let status = status(&http, profile).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                println!("Profile:    {}", status.profile);
+                if let (Some(login), Some(user_id)) = (&status.login, &status.user_id) {
+                    println!("User:       {} ({})", login, user_id);
+                }
+                println!("Client id:  {}", status.client_id);
+                println!(
+                    "Expires in: {}",
+                    status
+                        .expires_in
+                        .map(format_duration)
+                        .unwrap_or_else(|| "never".to_string())
+                );
+                println!("Scopes:     {}", status.scopes.join(" "));
+                if !status.missing_scopes.is_empty() {
+                    println!("Missing:    {}", status.missing_scopes.join(" "));
+                }
+            }
+            Ok(())
+        }
+        AuthSubcommand::Scopes { json } => {
+            let TokenStatus {
+                scopes,
+                missing_scopes,
+                ..
+            } = status(&http, profile).await?;
+            if json {
+                println!(
+                    "{}",
+                    serde_json::json!({ "scopes": scopes, "missing_scopes": missing_scopes })
+                );
+            } else {
+                for scope in scopes {
+                    println!("{}", scope);
+                }
+                for scope in missing_scopes {
+                    println!("{} (missing)", scope);
+                }
+            }
+            Ok(())
+        }
+        AuthSubcommand::Revoke => revoke(&http, profile).await,
+    }
+}
+
+/// Validates the token of `profile`, failing if it is expired or was revoked
+pub async fn status(
+    http: &TwitchHttp,
+    profile: Option<&str>,
+) -> Result<TokenStatus, Box<dyn Error>> {
+    let env = load_env(profile)?;
+    match validate_token(|req| http.oauth(req), &AccessToken::new(env.token)).await {
+        Ok(token) => Ok(TokenStatus::new(&env.profile, token)),
+        Err(ValidationError::NotAuthorized) => Err(AuthError::Invalid(env.profile).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Revokes the token of `profile` and removes it, only removing it if it is invalid already
+pub async fn revoke(http: &TwitchHttp, profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let env = load_env(profile)?;
+    let token = AccessToken::new(env.token.clone());
+    let client_id = match validate_token(|req| http.oauth(req), &token).await {
+        Ok(validated) => validated.client_id,
+        Err(ValidationError::NotAuthorized) => {
+            remove_token(&env.profile, &env.source, &env.token)?;
+            println!("The token was already invalid and has been removed.");
+            return Ok(());
+        }
+        Err(e) => match env.client_id {
+            Some(client_id) => ClientId::new(client_id),
+            None => return Err(e.into()),
+        },
+    };
+    revoke_token(|req| http.oauth(req), &token, &client_id).await?;
+    remove_token(&env.profile, &env.source, &env.token)?;
+    println!("Revoked the token of profile `{}`.", env.profile);
+    Ok(())
+}
+
+/// Logs in with the authorization code flow if `browser` is given, with the device flow otherwise
+pub async fn login(
+    http: &TwitchHttp,
+    profile: &str,
+    client_id: &str,
+    browser: Option<Browser<'_>>,
+    encrypt: bool,
+) -> Result<(), Box<dyn Error>> {
+    let source = if encrypt {
+        TokenSource::Encrypted(passphrase(true)?)
+    } else {
+        let stored = load_profile(Some(profile))?;
+        if stored.token_command().is_some() {
+            TokenSource::Command(stored.store_command)
+        } else {
+            TokenSource::Profile
+        }
+    };
+
+    let scopes = scope_string(SCOPES);
+    let client_secret = browser.as_ref().map(|browser| browser.client_secret);
+    let stored_secret = browser
+        .as_ref()
+        .filter(|browser| browser.store_secret)
+        .map(|browser| browser.client_secret.to_string());
+    let token = match browser {
+        Some(browser) => {
+            let (code, redirect_uri) =
+                authorize_in_browser(http, client_id, &scopes, browser.port, browser.open).await?;
+            post_form(
+                http,
+                "token",
+                &[
+                    ("client_id", client_id),
+                    ("client_secret", browser.client_secret),
+                    ("code", &code),
+                    ("grant_type", "authorization_code"),
+                    ("redirect_uri", &redirect_uri),
+                ],
+            )
+            .await?
+            .map_err(AuthError::Twitch)?
+        }
+        None => {
+            let device: DeviceCode = post_form(
+                http,
+                "device",
+                &[("client_id", client_id), ("scopes", &scopes)],
+            )
+            .await?
+            .map_err(AuthError::Twitch)?;
+
+            println!(
+                "Open {} and enter the code `{}` to authorize twitchctl.",
+                device.verification_uri, device.user_code
+            );
+            poll_device_token(http, client_id, &scopes, &device).await?
+        }
+    };
+
+    let user = UserToken::from_existing(
+        |req| http.oauth(req),
+        AccessToken::new(token.access_token.clone()),
+        token.refresh_token.clone().map(RefreshToken::new),
+        client_secret.map(|secret| ClientSecret::new(secret.to_string())),
+    )
+    .await?;
+
+    let stored_in = save_token(
+        profile,
+        &source,
+        token.access_token,
+        token.refresh_token,
+        Some(client_id.to_string()),
+        stored_secret,
+    )?;
+    println!(
+        "Logged in as `{}`, the token was stored as profile `{}` in {}.",
+        user.login, profile, stored_in
+    );
+    Ok(())
+}
+
+/// Exchanges a refresh token for a new token
+///
+/// The rotated token is stored in `profile` for further use, along with the
+/// client secret if `store_secret` is set.
+pub async fn refresh_token(
+    http: &TwitchHttp,
+    profile: &str,
+    source: &TokenSource,
+    client_id: &str,
+    refresh_token: &str,
+    client_secret: Option<&str>,
+    store_secret: bool,
+) -> Result<UserToken, Box<dyn Error>> {
+    let mut form = vec![
+        ("client_id", client_id),
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+    ];
+    if let Some(secret) = client_secret {
+        form.push(("client_secret", secret));
+    }
+    let response: TokenResponse = post_form(http, "token", &form)
+        .await?
+        .map_err(AuthError::Twitch)?;
+
+    let token = UserToken::from_existing(
+        |req| http.oauth(req),
+        AccessToken::new(response.access_token.clone()),
+        response.refresh_token.clone().map(RefreshToken::new),
+        client_secret.map(|secret| ClientSecret::new(secret.to_string())),
+    )
+    .await?;
+    save_token(
+        profile,
+        source,
+        response.access_token,
+        response.refresh_token,
+        Some(client_id.to_string()),
+        client_secret.filter(|_| store_secret).map(str::to_string),
+    )?;
+    Ok(token)
+}
+
+async fn poll_device_token(
+    http: &TwitchHttp,
+    client_id: &str,
+    scopes: &str,
+    device: &DeviceCode,
+) -> Result<TokenResponse, Box<dyn Error>> {
+    let mut interval = device.interval.max(1);
+    let mut remaining = device.expires_in;
+    loop {
+        if remaining == 0 {
+            return Err(AuthError::Expired.into());
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        remaining = remaining.saturating_sub(interval);
+
+        match post_form(
+            http,
+            "token",
+            &[
+                ("client_id", client_id),
+                ("scopes", scopes),
+                ("device_code", &device.device_code),
+                ("grant_type", DEVICE_CODE_GRANT),
+            ],
+        )
+        .await?
+        {
+            Ok(token) => return Ok(token),
+            Err(message) => match message.as_str() {
+                "authorization_pending" => {}
+                "slow_down" => interval += 5,
+                "expired_token" => return Err(AuthError::Expired.into()),
+                "access_denied" => return Err(AuthError::Denied.into()),
+                _ => return Err(AuthError::Twitch(message).into()),
+            },
+        }
+    }
+}
+
+/// Waits for the redirect of the authorization code flow on `port` and
+/// returns the code and the redirect uri it was sent to
+///
+/// The redirect has to carry the random state sent with the authorize url,
+/// otherwise it was not started by this login and is rejected.
+async fn authorize_in_browser(
+    http: &TwitchHttp,
+    client_id: &str,
+    scopes: &str,
+    port: u16,
+    open: Box<dyn FnOnce(&Url) + '_>,
+) -> Result<(String, String), Box<dyn Error>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    let redirect_uri = format!("http://{}", listener.local_addr()?);
+
+    let mut state = [0; 16];
+    rand::thread_rng().fill_bytes(&mut state);
+    let state = base64::encode_config(state, base64::URL_SAFE_NO_PAD);
+    let url = Url::parse_with_params(
+        &format!("{}/authorize", http.endpoints().oauth_url),
+        &[
+            ("client_id", client_id),
+            ("redirect_uri", &redirect_uri),
+            ("response_type", "code"),
+            ("scope", scopes),
+            ("state", &state),
+        ],
+    )?;
+    open(&url);
+
+    let code = tokio::time::timeout(BROWSER_LOGIN_TIMEOUT, async {
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+            if let Some(code) = handle_redirect(&mut stream, &state).await? {
+                return Ok::<_, Box<dyn Error>>(code);
+            }
+        }
+    })
+    .await
+    .map_err(|_| AuthError::Timeout)??;
+    Ok((code, redirect_uri))
+}
+
+/// Answers a request to the redirect listener
+///
+/// Returns `None` for requests that are not the redirect, like the browser asking for a favicon.
+async fn handle_redirect(
+    stream: &mut TcpStream,
+    state: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut reader = BufReader::new(&mut *stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // skip the headers, the request has no body
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let url = Url::parse(&format!("http://localhost{}", target))?;
+    let query = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    };
+
+    if query("state").is_none() {
+        respond(stream, "404 Not Found", "Not found.").await?;
+        return Ok(None);
+    }
+    if query("state").as_deref() != Some(state) {
+        respond(stream, "400 Bad Request", "The login was aborted.").await?;
+        return Err(AuthError::StateMismatch.into());
+    }
+    match (query("code"), query("error")) {
+        (Some(code), _) => {
+            respond(
+                stream,
+                "200 OK",
+                "twitchctl was authorized, you can close this window.",
+            )
+            .await?;
+            Ok(Some(code))
+        }
+        (None, error) => {
+            respond(stream, "400 Bad Request", "The login was not authorized.").await?;
+            Err(match error.as_deref() {
+                Some("access_denied") | None => AuthError::Denied,
+                Some(_) => {
+                    AuthError::Twitch(query("error_description").or(error).unwrap_or_default())
+                }
+            }
+            .into())
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) -> std::io::Result<()> {
+    let body = format!(
+        "<!DOCTYPE html><html><head><title>twitchctl</title></head><body><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Posts a form to an OAuth endpoint, returning twitch's error message on failure
+async fn post_form<T: DeserializeOwned>(
+    http: &TwitchHttp,
+    endpoint: &str,
+    form: &[(&str, &str)],
+) -> Result<Result<T, String>, Box<dyn Error>> {
+    let res = http.post_form(endpoint, form).await?;
+    if res.status().is_success() {
+        Ok(Ok(serde_json::from_slice(res.body())?))
+    } else {
+        let body = String::from_utf8_lossy(res.body()).into_owned();
+        Ok(Err(serde_json::from_str::<ErrorResponse>(&body)
+            .map(|e| e.message)
+            .unwrap_or(body)))
+    }
+}
+
+/// Returns the scopes in `needed` that are neither in `granted` nor implied by them
+pub fn missing_scopes(granted: &[Scope], needed: &[Scope]) -> Vec<Scope> {
+    needed
+        .iter()
+        .filter(|needed| {
+            !granted.iter().any(|granted| {
+                granted == *needed
+                    || IMPLIED_SCOPES
+                        .iter()
+                        .any(|(implying, implied)| granted == implying && needed == &implied)
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+fn format_duration(secs: u64) -> String {
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+fn scope_string(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use twitch_api2::helix::tags::TwitchTag;
+
+use crate::config::cache_dir;
+
+/// Bumped when the format of the cache file changes, files of other versions are ignored
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheFile<T> {
+    version: u32,
+    /// the helix url the tags were fetched from, to not mix up Twitch's with a mock's
+    helix_url: String,
+    /// seconds since the unix epoch
+    fetched_at: u64,
+    tags: Vec<T>,
+}
+
+/// The tag catalogue cached on disk
+///
+/// Fetching all tags takes several requests, so [`ApiClient::get_all_tags`]
+/// uses the cached tags while they are younger than the ttl, and regardless of
+/// their age when Twitch can't be reached.
+///
+/// [`ApiClient::get_all_tags`]: crate::ApiClient::get_all_tags
+#[derive(Clone, Debug)]
+pub struct TagCache {
+    file: PathBuf,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl TagCache {
+    /// The cache in the platform specific cache folder, `None` if there is none
+    ///
+    /// With `refresh` the tags are fetched again even if the cached ones are fresh.
+    pub fn new(ttl: Duration, refresh: bool) -> Option<TagCache> {
+        cache_dir().map(|dir| TagCache::in_dir(dir, ttl, refresh))
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>, ttl: Duration, refresh: bool) -> TagCache {
+        TagCache {
+            file: dir.into().join("tags.json"),
+            ttl,
+            refresh,
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The cached tags if they are younger than the ttl and no refresh was asked for
+    pub fn fresh(&self, helix_url: &str) -> Option<Vec<TwitchTag>> {
+        if self.refresh {
+            return None;
+        }
+        let (fetched_at, tags) = self.load(helix_url)?;
+        match fetched_at.elapsed() {
+            Ok(age) if age < self.ttl => Some(tags),
+            _ => None,
+        }
+    }
+
+    /// The cached tags regardless of their age, with the time they were fetched
+    pub fn load(&self, helix_url: &str) -> Option<(SystemTime, Vec<TwitchTag>)> {
+        let cached: CacheFile<TwitchTag> = serde_json::from_slice(&fs::read(&self.file).ok()?)
+            .ok()
+            .filter(|cached: &CacheFile<_>| {
+                cached.version == CACHE_VERSION && cached.helix_url == helix_url
+            })?;
+        Some((
+            UNIX_EPOCH + Duration::from_secs(cached.fetched_at),
+            cached.tags,
+        ))
+    }
+
+    pub fn store(&self, helix_url: &str, tags: &[TwitchTag]) -> Result<(), Box<dyn Error>> {
+        let cached = CacheFile {
+            version: CACHE_VERSION,
+            helix_url: helix_url.to_string(),
+            fetched_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            // in the format returned by helix, `TwitchTag` is not serializable
+            tags: tags
+                .iter()
+                .map(|tag| {
+                    json!({
+                        "tag_id": tag.id,
+                        "is_auto": tag.is_auto,
+                        "localization_names": tag.localization_names,
+                        "localization_descriptions": tag.localization_descriptions,
+                    })
+                })
+                .collect::<Vec<Value>>(),
+        };
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // replaced at once, so a concurrent twitchctl never reads half a file
+        let partial = self.file.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(&cached)?)?;
+        fs::rename(&partial, &self.file)?;
+        Ok(())
+    }
+}
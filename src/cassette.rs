@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use twitch_api2::{
+    client::{BoxedFuture, Req, Response},
+    twitch_oauth2::oauth2::url::form_urlencoded,
+};
+
+use crate::http::{HttpBackend, HttpError};
+
+/// Bumped when the format of cassettes changes
+const CASSETTE_VERSION: u32 = 1;
+
+/// Fields of JSON and form bodies holding secrets
+const SECRET_FIELDS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "client_secret",
+    "code",
+    "device_code",
+    "token",
+];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(thiserror::Error, Debug)]
+pub enum CassetteError {
+    #[error("Could not read the cassette `{}`: {1}", .0.display())]
+    Read(PathBuf, String),
+    #[error("The cassette `{}` was recorded by an unsupported version of twitchctl", .0.display())]
+    Version(PathBuf),
+    #[error("The cassette has no response left for {0} {1}")]
+    NoResponse(String, String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Recorded {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RecordedResponse {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Interaction {
+    request: Recorded,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Cassette {
+    version: u32,
+    interactions: Vec<Interaction>,
+}
+
+fn headers(headers: &http::HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            let value = if name == http::header::AUTHORIZATION {
+                // keep the scheme, like `Bearer`, to see which kind of token was used
+                match value.split_once(' ') {
+                    Some((scheme, _)) => format!("{} {}", scheme, REDACTED),
+                    None => REDACTED.to_string(),
+                }
+            } else {
+                value
+            };
+            (name.as_str().to_string(), value)
+        })
+        .collect()
+}
+
+/// The body with the values of [`SECRET_FIELDS`] redacted, if it is JSON or a form
+fn redact(body: &[u8]) -> String {
+    if let Ok(mut json) = serde_json::from_slice::<Value>(body) {
+        redact_json(&mut json);
+        return json.to_string();
+    }
+    let body = String::from_utf8_lossy(body);
+    if body.contains('=') && !body.contains(char::is_whitespace) {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(form_urlencoded::parse(body.as_bytes()).map(|(key, value)| {
+                let value = if SECRET_FIELDS.contains(&key.as_ref()) {
+                    REDACTED.into()
+                } else {
+                    value
+                };
+                (key, value)
+            }))
+            .finish()
+    } else {
+        body.into_owned()
+    }
+}
+
+fn redact_json(json: &mut Value) {
+    match json {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) && value.is_string() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Sends the requests with another backend and records them with their responses to a cassette
+///
+/// Tokens and secrets are redacted, the cassette is written after every
+/// response so it is complete even if twitchctl fails afterwards.
+pub struct Recorder {
+    backend: Arc<dyn HttpBackend>,
+    file: PathBuf,
+    cassette: Mutex<Cassette>,
+}
+
+impl Recorder {
+    pub fn new(backend: Arc<dyn HttpBackend>, file: impl Into<PathBuf>) -> Recorder {
+        Recorder {
+            backend,
+            file: file.into(),
+            cassette: Mutex::new(Cassette {
+                version: CASSETTE_VERSION,
+                interactions: vec![],
+            }),
+        }
+    }
+
+    fn record(&self, request: Recorded, response: &Response) -> Result<(), HttpError> {
+        let mut cassette = self.cassette.lock().expect("not poisoned");
+        cassette.interactions.push(Interaction {
+            request,
+            response: RecordedResponse {
+                status: response.status().as_u16(),
+                headers: headers(response.headers()),
+                body: redact(response.body()),
+            },
+        });
+        let json =
+            serde_json::to_vec_pretty(&*cassette).map_err(|e| HttpError::Backend(Box::new(e)))?;
+        fs::write(&self.file, json).map_err(|e| HttpError::Backend(Box::new(e)))
+    }
+}
+
+impl HttpBackend for Recorder {
+    fn send(&self, request: Req) -> BoxedFuture<'_, Result<Response, HttpError>> {
+        let recorded = Recorded {
+            method: request.method().to_string(),
+            url: request.uri().to_string(),
+            headers: headers(request.headers()),
+            body: redact(request.body()),
+        };
+        Box::pin(async move {
+            let response = self.backend.send(request).await?;
+            self.record(recorded, &response)?;
+            Ok(response)
+        })
+    }
+
+    fn is_cassette(&self) -> bool {
+        true
+    }
+}
+
+/// Answers the requests with the responses of a cassette, without any network
+///
+/// A request is answered with the first unused response to a request with the
+/// same method, url and body, or else with the same method and url.
+pub struct Replayer {
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Replayer {
+    pub fn open(file: &Path) -> Result<Replayer, CassetteError> {
+        let read_error =
+            |e: &dyn std::error::Error| CassetteError::Read(file.into(), e.to_string());
+        let cassette: Cassette =
+            serde_json::from_slice(&fs::read(file).map_err(|e| read_error(&e))?)
+                .map_err(|e| read_error(&e))?;
+        if cassette.version != CASSETTE_VERSION {
+            return Err(CassetteError::Version(file.into()));
+        }
+        Ok(Replayer {
+            interactions: Mutex::new(cassette.interactions),
+        })
+    }
+
+    fn replay(&self, request: &Req) -> Result<Response, HttpError> {
+        let method = request.method().to_string();
+        let url = request.uri().to_string();
+        let body = redact(request.body());
+
+        let mut interactions = self.interactions.lock().expect("not poisoned");
+        let same = |i: &Interaction| i.request.method == method && i.request.url == url;
+        let position = interactions
+            .iter()
+            .position(|i| same(i) && i.request.body == body)
+            .or_else(|| interactions.iter().position(same))
+            .ok_or_else(|| HttpError::Backend(Box::new(CassetteError::NoResponse(method, url))))?;
+        let recorded = interactions.remove(position).response;
+
+        let mut response = http::Response::builder().status(recorded.status);
+        for (name, value) in &recorded.headers {
+            response = response.header(name.as_str(), value.as_str());
+        }
+        Ok(response.body(recorded.body.into_bytes())?)
+    }
+}
+
+impl HttpBackend for Replayer {
+    fn send(&self, request: Req) -> BoxedFuture<'_, Result<Response, HttpError>> {
+        let response = self.replay(&request);
+        Box::pin(async move { response })
+    }
+
+    fn is_cassette(&self) -> bool {
+        true
+    }
+}
@@ -1,16 +1,53 @@
 use std::{path::PathBuf, str::FromStr};
 
-use crate::rewards::RewardsOptions;
-use crate::tags::TagsOptions;
+use crate::{
+    auth::AuthOptions, preset::PresetSubcommand, rewards::RewardsOptions, tags::TagsOptions,
+    template::TemplateOptions,
+};
 use structopt::{
     clap::{AppSettings, Shell},
     StructOpt,
 };
+use twitch_api2::twitch_oauth2::Scope;
 
 /// A sane Twitch commandline interface
 #[derive(Debug, StructOpt)]
 #[structopt(name = "twitchctl", global_settings = &[AppSettings::DeriveDisplayOrder])]
 pub struct CliOptions {
+    /// the profile to use, from `profiles.toml` in the config folder
+    ///
+    /// Profiles hold a token and defaults for `locale` and `broadcaster`.
+    /// Without a profile `TWITCHCTL_TOKEN` is used if set, otherwise
+    /// the `default` profile.
+    #[structopt(long, global = true, env = "TWITCHCTL_PROFILE")]
+    pub profile: Option<String>,
+    /// use an app access token instead of the token of a user
+    ///
+    /// The token is requested with the client id and secret of the profile or
+    /// `TWITCHCTL_CLIENT_ID` and `TWITCHCTL_CLIENT_SECRET`. It can only be
+    /// used to read public information, like `tags list-all` and `search`.
+    #[structopt(long, global = true)]
+    pub app_token: bool,
+    /// fetch the tag catalogue from Twitch instead of using the cached one
+    ///
+    /// The catalogue is cached for a day, or `tag_cache_ttl` seconds of the
+    /// profile or `TWITCHCTL_TAG_CACHE_TTL`.
+    #[structopt(long, global = true)]
+    pub refresh: bool,
+    /// record all requests to Twitch and their responses to a cassette file
+    ///
+    /// Tokens and secrets are redacted, the cassette can be replayed with `--replay`.
+    #[structopt(long, global = true, value_name = "file", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// answer all requests from a cassette recorded with `--record`, without any network
+    #[structopt(long, global = true, value_name = "file")]
+    pub replay: Option<PathBuf>,
+    /// print the requests that would change the channel instead of sending them
+    ///
+    /// Each request is printed with its method, endpoint and JSON body,
+    /// requests only reading from Twitch are still sent.
+    #[structopt(long, global = true)]
+    pub dry_run: bool,
     #[structopt(subcommand)]
     pub category: Category,
 }
@@ -54,8 +91,46 @@ pub enum Category {
         #[structopt(long)]
         noenv: bool,
         file: PathBuf,
+        #[structopt(flatten)]
+        template: TemplateOptions,
+    },
+    /// shows what applying a config file would change
+    ///
+    /// Compares the title, category, language, tags and rewards of the channel
+    /// with the config file, nothing is changed.
+    Plan {
+        /// Environment variables will be ignored
+        #[structopt(long)]
+        noenv: bool,
+        file: PathBuf,
+        #[structopt(flatten)]
+        template: TemplateOptions,
     },
-    /// applies a stream configuration from a preset
+    /// shows what applying a config file changes and applies it
+    ///
+    /// Only the calls needed to change the channel are made.
+    Apply {
+        /// Environment variables will be ignored
+        #[structopt(long)]
+        noenv: bool,
+        file: PathBuf,
+        #[structopt(flatten)]
+        template: TemplateOptions,
+    },
+    /// saves the title, category, language, tags and enabled rewards of the channel
+    ///
+    /// They are written as config file in the format of its extension, so a
+    /// stream setup can be saved as preset. Without `--output` they are
+    /// printed as TOML.
+    Snapshot {
+        /// the file to write the config to
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+        /// the locale to name the tags in
+        #[structopt(long)]
+        config_locale: Option<String>,
+    },
+    /// applies or manages the stream configurations stored as presets
     ///
     /// Preset files are stored in the platform specific
     /// config folders and are matched fuzzily
@@ -69,17 +144,58 @@ pub enum Category {
     /// They follow the same syntax and restrictions as config files.
     ///
     /// They can also be overridden with environment variables.
+    ///
+    /// Presets and config files can extend presets with `extends`, their
+    /// values are laid over the ones of the extended presets and their tags
+    /// and rewards appended, unless listed in `replace`.
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp)]
     Preset {
         /// Environment variables will be ignored
         #[structopt(long)]
         noenv: bool,
-        query: String,
+        /// the preset to apply
+        ///
+        /// A preset named like a subcommand is applied with `preset -- <query>`.
+        query: Option<String>,
+        #[structopt(flatten)]
+        template: TemplateOptions,
+        #[structopt(subcommand)]
+        subcommand: Option<PresetSubcommand>,
     },
     /// creates or manages rewards
     Reward {
         #[structopt(flatten)]
         options: RewardsOptions,
     },
+    /// logs in to twitch or manages the stored token
+    Auth {
+        #[structopt(flatten)]
+        options: AuthOptions,
+    },
+}
+
+impl Category {
+    /// The scopes needed to run this command
+    ///
+    /// Config files and presets check their scopes once they are parsed.
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Category::Tags { options } => options.subcommand.scopes(),
+            Category::Reward { options } => options.subcommand.scopes(),
+            Category::Snapshot { .. } => vec![Scope::ChannelReadRedemptions],
+            Category::Preset {
+                subcommand: Some(subcommand),
+                ..
+            } => subcommand.scopes(),
+            Category::Search { .. }
+            | Category::Completions { .. }
+            | Category::File { .. }
+            | Category::Plan { .. }
+            | Category::Apply { .. }
+            | Category::Preset { .. }
+            | Category::Auth { .. } => vec![],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -106,9 +222,9 @@ impl FromStr for ShellType {
     }
 }
 
-impl Into<Shell> for &ShellType {
-    fn into(self) -> Shell {
-        match self {
+impl From<&ShellType> for Shell {
+    fn from(shell: &ShellType) -> Shell {
+        match shell {
             ShellType::Bash => Shell::Bash,
             ShellType::Fish => Shell::Fish,
             ShellType::Zsh => Shell::Zsh,
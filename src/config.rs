@@ -1,19 +1,473 @@
-use dotenv;
+use figment::{
+    providers::{Format, Toml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fs, path::PathBuf, time::Duration};
+
+use crate::credentials::{
+    passphrase, read_encrypted, run_store_command, run_token_command, write_encrypted,
+    write_private, Tokens,
+};
+use crate::warning;
 
 const TOKEN_VAR: &str = "TWITCHCTL_TOKEN";
+const REFRESH_TOKEN_VAR: &str = "TWITCHCTL_REFRESH_TOKEN";
+const TOKEN_COMMAND_VAR: &str = "TWITCHCTL_TOKEN_COMMAND";
+const CLIENT_ID_VAR: &str = "TWITCHCTL_CLIENT_ID";
+const CLIENT_SECRET_VAR: &str = "TWITCHCTL_CLIENT_SECRET";
+const HELIX_URL_VAR: &str = "TWITCHCTL_HELIX_URL";
+const OAUTH_URL_VAR: &str = "TWITCHCTL_OAUTH_URL";
+const TAG_CACHE_TTL_VAR: &str = "TWITCHCTL_TAG_CACHE_TTL";
+
+/// How long the tag catalogue is cached, a day
+const DEFAULT_TAG_CACHE_TTL: u64 = 24 * 60 * 60;
+
+pub const TWITCH_HELIX_URL: &str = "https://api.twitch.tv/helix";
+pub const TWITCH_OAUTH_URL: &str = "https://id.twitch.tv/oauth2";
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DEFAULT_LOCALE: &str = "en-us";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error(
+        "Your Twitch oauth token is missing!\n\
+        Run `twitchctl auth login` or set {} in .env or your env vars.",
+        TOKEN_VAR
+    )]
+    NoToken,
+    #[error(
+        "The profile `{0}` has no token!\n\
+        Run `twitchctl --profile {0} auth login` to log in."
+    )]
+    NoProfileToken(String),
+    #[error(
+        "An app access token needs a client id and secret!\n\
+        Set `client_id` and `client_secret` of the profile or {} and {} \
+        in .env or your env vars.",
+        CLIENT_ID_VAR,
+        CLIENT_SECRET_VAR
+    )]
+    NoClientCredentials,
+    #[error("Failed to run `token_command`: {0}")]
+    TokenCommand(String),
+    #[error("Failed to parse `{}`: {}", .0.display(), .1)]
+    Profiles(PathBuf, Box<figment::Error>),
+    #[error("Could not find the config Home. Maybe set XDG_CONFIG_HOME")]
+    NoConfigDir,
+}
 
 pub struct DotEnv {
     pub token: String,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Whether `client_secret` is stored with rotated tokens, it is not when
+    /// it came from `TWITCHCTL_CLIENT_SECRET`
+    pub store_client_secret: bool,
+    /// The profile rotated tokens are stored in
+    pub profile: String,
+    /// Where the token was loaded from and rotated tokens are stored to
+    pub source: TokenSource,
+    pub locale: Option<String>,
+    pub broadcaster: Option<String>,
+    pub endpoints: Endpoints,
+}
+
+/// The client credentials and defaults used with an app access token
+pub struct AppEnv {
+    pub client_id: String,
+    pub client_secret: String,
+    pub profile: String,
+    pub locale: Option<String>,
+    pub broadcaster: Option<String>,
+    pub endpoints: Endpoints,
+}
+
+/// The base urls of Twitch's APIs
+///
+/// They can be pointed at another server, like `twitch mock-api`, for testing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoints {
+    pub helix_url: String,
+    pub oauth_url: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints {
+            helix_url: TWITCH_HELIX_URL.to_string(),
+            oauth_url: TWITCH_OAUTH_URL.to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// The urls of the profile, `TWITCHCTL_HELIX_URL` and `TWITCHCTL_OAUTH_URL` or Twitch's
+    fn new(profile: &Profile) -> Endpoints {
+        let url = |stored: &Option<String>, var: &str, default: &str| {
+            stored
+                .clone()
+                .or_else(|| dotenv::var(var).ok())
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| default.to_string())
+        };
+        Endpoints {
+            helix_url: url(&profile.helix_url, HELIX_URL_VAR, TWITCH_HELIX_URL),
+            oauth_url: url(&profile.oauth_url, OAUTH_URL_VAR, TWITCH_OAUTH_URL),
+        }
+    }
+}
+
+/// The places a token can be loaded from, in the order they are tried
+#[derive(Clone, Debug)]
+pub enum TokenSource {
+    /// `TWITCHCTL_TOKEN` from `.env` or the environment, rotated tokens are not stored
+    Env,
+    /// the output of `token_command`, rotated tokens are passed to `store_command`
+    Command(Option<String>),
+    /// the profile's token file encrypted with the passphrase
+    Encrypted(String),
+    /// the tokens in `profiles.toml`
+    Profile,
+}
+
+/// A named identity in the profiles file
+///
+/// The tokens are set by `twitchctl auth login`, the remaining fields are
+/// defaults for the commands run with this profile.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Profile {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    /// the client secret of a confidential application, set by `auth login --browser`
+    /// unless the tokens are stored elsewhere
+    pub client_secret: Option<String>,
+    /// shell command printing the access token and optionally the refresh token
+    pub token_command: Option<String>,
+    /// shell command receiving rotated tokens in the format of `token_command`
+    pub store_command: Option<String>,
+    /// the locale to use for tag names
+    pub locale: Option<String>,
+    /// the broadcaster to use when none is specified
+    pub broadcaster: Option<String>,
+    /// the base url of the Helix API, for testing against another server
+    pub helix_url: Option<String>,
+    /// the base url of the OAuth API, for testing against another server
+    pub oauth_url: Option<String>,
+    /// how many seconds the tag catalogue is cached, `0` fetches it every time
+    pub tag_cache_ttl: Option<u64>,
+}
+
+impl Profile {
+    /// The `token_command` of the profile or `TWITCHCTL_TOKEN_COMMAND`
+    pub fn token_command(&self) -> Option<String> {
+        self.token_command
+            .clone()
+            .or_else(|| dotenv::var(TOKEN_COMMAND_VAR).ok())
+    }
+
+    /// How long the tag catalogue is cached, from the profile or `TWITCHCTL_TAG_CACHE_TTL`
+    pub fn cache_ttl(&self) -> Duration {
+        let secs = self.tag_cache_ttl.or_else(|| {
+            dotenv::var(TAG_CACHE_TTL_VAR)
+                .ok()
+                .and_then(|ttl| ttl.parse().ok())
+        });
+        Duration::from_secs(secs.unwrap_or(DEFAULT_TAG_CACHE_TTL))
+    }
+}
+
+/// Loads the token and defaults for a profile
+///
+/// The token is taken from the first of these that is set:
+///
+/// 1. `TWITCHCTL_TOKEN` from `.env` or the environment, unless a profile was selected
+/// 2. the output of the profile's `token_command` or `TWITCHCTL_TOKEN_COMMAND`,
+///    if the command fails the next source is tried
+/// 3. the profile's encrypted token file, see `twitchctl auth login --encrypt`
+/// 4. `access_token` of the profile in `profiles.toml`
+pub fn load_env(profile: Option<&str>) -> Result<DotEnv, Box<dyn Error>> {
+    let name = profile.unwrap_or(DEFAULT_PROFILE);
+    let stored = load_profile(profile)?;
+
+    let (tokens, source) = if let (None, Ok(token)) = (profile, dotenv::var(TOKEN_VAR)) {
+        let tokens = Tokens {
+            access_token: token,
+            refresh_token: dotenv::var(REFRESH_TOKEN_VAR).ok(),
+            client_secret: None,
+        };
+        (tokens, TokenSource::Env)
+    } else if let Some(tokens) = stored
+        .token_command()
+        .and_then(|command| command_tokens(&command))
+    {
+        (tokens, TokenSource::Command(stored.store_command.clone()))
+    } else if let Some(file) = encrypted_token_file(name).filter(|file| file.is_file()) {
+        let passphrase = passphrase(false)?;
+        let tokens = read_encrypted(&file, &passphrase)?;
+        (tokens, TokenSource::Encrypted(passphrase))
+    } else if let Some(token) = stored.access_token.clone() {
+        let tokens = Tokens {
+            access_token: token,
+            refresh_token: stored.refresh_token.clone(),
+            client_secret: None,
+        };
+        (tokens, TokenSource::Profile)
+    } else if profile.is_some() {
+        return Err(ConfigError::NoProfileToken(name.to_string()).into());
+    } else {
+        return Err(ConfigError::NoToken.into());
+    };
+
+    let endpoints = Endpoints::new(&stored);
+    let stored_secret = tokens.client_secret.or(stored.client_secret);
+    Ok(DotEnv {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        client_id: stored.client_id.or_else(client_id),
+        store_client_secret: stored_secret.is_some(),
+        client_secret: stored_secret.or_else(client_secret),
+        profile: name.to_string(),
+        source,
+        endpoints,
+        locale: stored.locale,
+        broadcaster: stored.broadcaster,
+    })
+}
+
+/// The tokens printed by `command`, `None` with a warning if it failed
+fn command_tokens(command: &str) -> Option<Tokens> {
+    match run_token_command(command) {
+        Ok(tokens) => Some(tokens),
+        Err(e) => {
+            warning!(
+                "{}, trying the next token source.",
+                ConfigError::TokenCommand(e.to_string())
+            );
+            None
+        }
+    }
+}
+
+/// Loads the client credentials and defaults of a profile for requesting an app access token
+///
+/// The client id and secret of the profile take precedence over
+/// `TWITCHCTL_CLIENT_ID` and `TWITCHCTL_CLIENT_SECRET`.
+pub fn load_app_env(profile: Option<&str>) -> Result<AppEnv, ConfigError> {
+    let name = profile.unwrap_or(DEFAULT_PROFILE);
+    let stored = load_profile(profile)?;
+
+    match (
+        stored.client_id.clone().or_else(client_id),
+        stored.client_secret.clone().or_else(client_secret),
+    ) {
+        (Some(client_id), Some(client_secret)) => Ok(AppEnv {
+            client_id,
+            client_secret,
+            profile: name.to_string(),
+            endpoints: Endpoints::new(&stored),
+            locale: stored.locale,
+            broadcaster: stored.broadcaster,
+        }),
+        _ => Err(ConfigError::NoClientCredentials),
+    }
+}
+
+/// The credential used in place of the redacted ones when replaying a cassette
+const REPLAY_CREDENTIAL: &str = "[redacted]";
+
+/// The defaults of a profile with placeholder credentials, for replaying a cassette
+///
+/// Cassettes contain no secrets, so none are loaded.
+pub fn load_replay_env(profile: Option<&str>) -> Result<DotEnv, ConfigError> {
+    let stored = load_profile(profile)?;
+    Ok(DotEnv {
+        token: REPLAY_CREDENTIAL.to_string(),
+        refresh_token: None,
+        client_id: None,
+        client_secret: None,
+        store_client_secret: false,
+        profile: profile.unwrap_or(DEFAULT_PROFILE).to_string(),
+        source: TokenSource::Env,
+        endpoints: Endpoints::new(&stored),
+        locale: stored.locale,
+        broadcaster: stored.broadcaster,
+    })
+}
+
+/// Like [`load_replay_env`], for replaying a cassette recorded with an app access token
+pub fn load_replay_app_env(profile: Option<&str>) -> Result<AppEnv, ConfigError> {
+    let stored = load_profile(profile)?;
+    Ok(AppEnv {
+        client_id: REPLAY_CREDENTIAL.to_string(),
+        client_secret: REPLAY_CREDENTIAL.to_string(),
+        profile: profile.unwrap_or(DEFAULT_PROFILE).to_string(),
+        endpoints: Endpoints::new(&stored),
+        locale: stored.locale,
+        broadcaster: stored.broadcaster,
+    })
+}
+
+pub fn client_id() -> Option<String> {
+    dotenv::var(CLIENT_ID_VAR).ok()
 }
 
-pub fn load_env() -> DotEnv {
-    let token = match dotenv::var(TOKEN_VAR) {
-        Ok(token) => token,
-        Err(_) => panic!(
-            "Your Twitch oauth token is missing!\r\n{} should exist in .env or your env vars.",
-            TOKEN_VAR
-        ),
+pub fn client_secret() -> Option<String> {
+    dotenv::var(CLIENT_SECRET_VAR).ok()
+}
+
+/// The base urls of Twitch's APIs for a profile, see [`Endpoints`]
+pub fn load_endpoints(profile: Option<&str>) -> Result<Endpoints, ConfigError> {
+    Ok(Endpoints::new(&load_profile(profile)?))
+}
+
+/// The stored settings of a profile, empty if it does not exist
+pub fn load_profile(profile: Option<&str>) -> Result<Profile, ConfigError> {
+    Ok(load_profiles()?
+        .remove(profile.unwrap_or(DEFAULT_PROFILE))
+        .unwrap_or_default())
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|mut dir| {
+        dir.push("twitchctl");
+        dir
+    })
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|mut dir| {
+        dir.push("twitchctl");
+        dir
+    })
+}
+
+fn profiles_file() -> Option<PathBuf> {
+    config_dir().map(|mut dir| {
+        dir.push("profiles.toml");
+        dir
+    })
+}
+
+fn encrypted_token_file(profile: &str) -> Option<PathBuf> {
+    config_dir().map(|mut dir| {
+        dir.push("tokens");
+        dir.push(format!("{}.toml", profile));
+        dir
+    })
+}
+
+pub fn load_profiles() -> Result<BTreeMap<String, Profile>, ConfigError> {
+    match profiles_file().filter(|file| file.is_file()) {
+        Some(file) => Figment::new()
+            .merge(Toml::file(&file))
+            .extract()
+            .map_err(|e| ConfigError::Profiles(file, Box::new(e))),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Stores the tokens of a profile in `source`, keeping its other settings
+///
+/// The client secret is stored along with the tokens, `None` removes a stored one.
+/// Returns a description of where the tokens were stored.
+pub fn save_token(
+    profile: &str,
+    source: &TokenSource,
+    access_token: String,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut profiles = load_profiles()?;
+    let entry = profiles.entry(profile.to_string()).or_default();
+    entry.client_id = client_id;
+    // the secret is stored with the tokens, in plain text only if they are
+    entry.client_secret = None;
+    let tokens = Tokens {
+        access_token,
+        refresh_token,
+        client_secret,
+    };
+
+    let stored_in = match source {
+        TokenSource::Env => {
+            // the variable is read again on the next run, saving the token elsewhere would not help
+            warning!(
+                "The token from {} was rotated, the variable now holds a stale token. \
+                Log in with `twitchctl auth login` or update {} and {}.",
+                TOKEN_VAR,
+                TOKEN_VAR,
+                REFRESH_TOKEN_VAR
+            );
+            "nowhere".to_string()
+        }
+        TokenSource::Profile => {
+            entry.access_token = Some(tokens.access_token);
+            entry.refresh_token = tokens.refresh_token;
+            entry.client_secret = tokens.client_secret;
+            format!("`{}`", save_profiles(&profiles)?.display())
+        }
+        TokenSource::Encrypted(passphrase) => {
+            let file = encrypted_token_file(profile).ok_or(ConfigError::NoConfigDir)?;
+            write_encrypted(&file, passphrase, &tokens)?;
+            // do not leave plaintext tokens behind
+            entry.access_token = None;
+            entry.refresh_token = None;
+            save_profiles(&profiles)?;
+            format!("`{}`", file.display())
+        }
+        TokenSource::Command(Some(command)) => {
+            run_store_command(command, &tokens)?;
+            save_profiles(&profiles)?;
+            format!("`{}`", command)
+        }
+        TokenSource::Command(None) => {
+            warning!(
+                "The token of profile `{}` was rotated, but it has no `store_command`. \
+                Update the secret printed by `token_command` by logging in again.",
+                profile
+            );
+            "nowhere".to_string()
+        }
     };
+    Ok(stored_in)
+}
+
+/// Removes the stored tokens of a profile
+///
+/// Tokens from `TWITCHCTL_TOKEN` or `token_command` are not stored
+/// and leave the profile untouched.
+pub fn remove_token(
+    profile: &str,
+    source: &TokenSource,
+    access_token: &str,
+) -> Result<(), Box<dyn Error>> {
+    match source {
+        TokenSource::Encrypted(_) => {
+            if let Some(file) = encrypted_token_file(profile).filter(|file| file.is_file()) {
+                fs::remove_file(file)?;
+            }
+        }
+        TokenSource::Profile => {
+            let mut profiles = load_profiles()?;
+            if let Some(entry) = profiles.get_mut(profile) {
+                if entry.access_token.as_deref() == Some(access_token) {
+                    entry.access_token = None;
+                    entry.refresh_token = None;
+                    save_profiles(&profiles)?;
+                }
+            }
+        }
+        TokenSource::Env | TokenSource::Command(_) => {}
+    }
+    Ok(())
+}
 
-    DotEnv { token }
+fn save_profiles(profiles: &BTreeMap<String, Profile>) -> Result<PathBuf, Box<dyn Error>> {
+    let file = profiles_file().ok_or(ConfigError::NoConfigDir)?;
+    write_private(&file, toml::to_string(profiles)?.as_bytes())?;
+    Ok(file)
 }
@@ -0,0 +1,192 @@
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    Aes256Gcm,
+};
+use hmac::Hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+const PASSPHRASE_VAR: &str = "TWITCHCTL_PASSPHRASE";
+const PBKDF2_ROUNDS: u32 = 200_000;
+const ENCRYPTION_VERSION: u32 = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CredentialError {
+    #[error("`{0}` exited with {1}")]
+    Command(String, ExitStatus),
+    #[error("`{0}` did not print a token")]
+    EmptyCommand(String),
+    #[error("Could not decrypt `{0}`, is the passphrase correct?")]
+    Decrypt(PathBuf),
+    #[error("`{0}` was encrypted by an unsupported version of twitchctl")]
+    Version(PathBuf),
+    #[error("The passphrases do not match")]
+    PassphraseMismatch,
+}
+
+/// The tokens as printed by `token_command` or stored in an encrypted file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// the client secret given to `auth login --browser`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Runs `command` in the shell, it prints the access token on the first and
+/// optionally the refresh token and the client secret on the next lines.
+pub fn run_token_command(command: &str) -> Result<Tokens, Box<dyn Error>> {
+    let output = shell(command).stderr(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(CredentialError::Command(command.to_string(), output.status).into());
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut lines = stdout.lines().map(str::trim).skip_while(|l| l.is_empty());
+    let access_token = lines.next();
+    // an empty line stands for a missing refresh token before the client secret
+    let mut optional = lines.map(|l| Some(l.to_string()).filter(|l| !l.is_empty()));
+    match access_token {
+        Some(access_token) => Ok(Tokens {
+            access_token: access_token.to_string(),
+            refresh_token: optional.next().flatten(),
+            client_secret: optional.next().flatten(),
+        }),
+        None => Err(CredentialError::EmptyCommand(command.to_string()).into()),
+    }
+}
+
+/// Runs `command` in the shell with the tokens in the format of `token_command` on stdin
+pub fn run_store_command(command: &str, tokens: &Tokens) -> Result<(), Box<dyn Error>> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    {
+        let stdin = child.stdin.as_mut().expect("stdin is piped");
+        writeln!(stdin, "{}", tokens.access_token)?;
+        match (&tokens.refresh_token, &tokens.client_secret) {
+            (refresh_token, Some(client_secret)) => writeln!(
+                stdin,
+                "{}\n{}",
+                refresh_token.as_deref().unwrap_or_default(),
+                client_secret
+            )?,
+            (Some(refresh_token), None) => writeln!(stdin, "{}", refresh_token)?,
+            (None, None) => {}
+        }
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CredentialError::Command(command.to_string(), status).into())
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(&["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", command]);
+        cmd
+    }
+}
+
+/// The passphrase from `TWITCHCTL_PASSPHRASE` or the terminal
+///
+/// When `confirm` is set, a passphrase entered in the terminal is asked for twice.
+pub fn passphrase(confirm: bool) -> Result<String, Box<dyn Error>> {
+    if let Ok(passphrase) = dotenv::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::read_password_from_tty(Some("Passphrase: "))?;
+    if confirm && passphrase != rpassword::read_password_from_tty(Some("Repeat passphrase: "))? {
+        return Err(CredentialError::PassphraseMismatch.into());
+    }
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+pub fn read_encrypted(file: &Path, passphrase: &str) -> Result<Tokens, Box<dyn Error>> {
+    let encrypted: EncryptedFile = toml::from_str(&fs::read_to_string(file)?)?;
+    if encrypted.version != ENCRYPTION_VERSION {
+        return Err(CredentialError::Version(file.to_path_buf()).into());
+    }
+    let key = derive_key(passphrase, &base64::decode(&encrypted.salt)?);
+    let plaintext = Aes256Gcm::new(GenericArray::from_slice(&key))
+        .decrypt(
+            GenericArray::from_slice(&base64::decode(&encrypted.nonce)?),
+            base64::decode(&encrypted.ciphertext)?.as_ref(),
+        )
+        .map_err(|_| CredentialError::Decrypt(file.to_path_buf()))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+pub fn write_encrypted(
+    file: &Path,
+    passphrase: &str,
+    tokens: &Tokens,
+) -> Result<(), Box<dyn Error>> {
+    let mut salt = [0; 16];
+    let mut nonce = [0; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt);
+    let ciphertext = Aes256Gcm::new(GenericArray::from_slice(&key))
+        .encrypt(
+            GenericArray::from_slice(&nonce),
+            serde_json::to_vec(tokens)?.as_ref(),
+        )
+        .expect("encrypting into a Vec does not fail");
+
+    let encrypted = EncryptedFile {
+        version: ENCRYPTION_VERSION,
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    };
+    write_private(file, toml::to_string(&encrypted)?.as_bytes())
+}
+
+/// Writes a file only readable by the current user
+pub fn write_private(file: &Path, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(file)?.write_all(content)?;
+    Ok(())
+}
@@ -1,143 +1,319 @@
-use crate::api::ChannelInfoBuilder;
+use crate::ini::{self, IniError};
+use crate::matches_any;
+use crate::plan::Plan;
+use crate::preset::{find_preset, PresetError};
+use crate::rewards::RewardConfig;
+use crate::template::Values;
 use crate::ApiClient;
-use crate::{exit, matches_any, warning};
 use figment::{
-    providers::{Env, Format, Toml, Yaml},
+    providers::{Env, Format, Json, Serialized, Toml, Yaml},
     Figment,
 };
-use futures::future::JoinAll;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::path::PathBuf;
-use twitch_api2::helix::points::{CustomReward, UpdateCustomRewardBody};
-
-#[derive(Deserialize, Debug)]
-struct Config {
-    config_locale: Option<String>,
-    tags: Option<Vec<String>>,
-    language: Option<String>,
-    title: Option<String>,
-    category: Option<String>,
-    notification: Option<String>,
+use std::fs;
+use std::path::{Path, PathBuf};
+use twitch_api2::twitch_oauth2::Scope;
+
+#[derive(thiserror::Error, Debug)]
+pub enum FileError {
+    #[error("Format not supported: `{0}`.")]
+    UnsupportedFormat(String),
+    #[error("Config file needs an extension defining the format.")]
+    NoExtension,
+    #[error("Failed to parse configuration: {0}")]
+    Parse(Box<figment::Error>),
+    #[error("Failed to parse configuration: {1} in `{}`", .0.display())]
+    Ini(PathBuf, IniError),
+    #[error("Could not find a category for `{0}`")]
+    NoCategory(String),
+    #[error(
+        "Could not apply the config, {0}{}{}",
+        list("Restored the changes already made:", .1),
+        list("Could not restore:", .2)
+    )]
+    RolledBack(String, Vec<String>, Vec<String>),
+    #[error("The title `{0}` has {1} characters, Twitch allows at most {2}.")]
+    TitleTooLong(String, usize, usize),
+    #[error("The notification has {0} characters, Twitch allows at most {1}.")]
+    NotificationTooLong(usize, usize),
+    #[error("Failed to write configuration: {0}")]
+    Serialize(String),
+    #[error("Could not find the preset `{0}` to extend: {1}")]
+    Extends(String, PresetError),
+    #[error("The presets extend each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("Only `tags` and `rewards` can be replaced, not `{0}`.")]
+    Replace(String),
+    #[error(
+        "The tags of the config and the presets it extends are named in different locales, {} and {}.\n\
+        Set the same `config_locale` in all of them or replace the tags.",
+        locale(.0),
+        locale(.1)
+    )]
+    TagLocales(Option<String>, Option<String>),
+    #[error("Unable to read `{}`: {1}", .0.display())]
+    Read(PathBuf, std::io::Error),
+    #[error("Unable to write `{}`: {1}", .0.display())]
+    Write(PathBuf, std::io::Error),
+}
+
+/// How a `config_locale` is named in errors
+fn locale(config_locale: &Option<String>) -> String {
+    match config_locale {
+        Some(locale) => format!("`{}`", locale),
+        None => "the locale of the profile".to_string(),
+    }
+}
+
+/// The lines below the header, nothing if there are none
+fn list(header: &str, lines: &[String]) -> String {
+    if lines.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}\n  {}", header, lines.join("\n  "))
+    }
+}
+
+/// A stream configuration, every field left out is not changed when applying it
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Config {
+    /// the presets this config is laid over, in order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extends: Option<Vec<String>>,
+    /// the lists replacing the ones of the extended presets instead of being appended to them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// the locale the tags are named in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// the go-live notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<String>,
+    /// the rewards to enable, all others are disabled
+    ///
+    /// Rewards given with their definition are created or updated to match it.
     // #[serde_as(deserialize_as = "Option<OneOrMany<_>>")]
-    rewards: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewards: Option<Vec<RewardConfig>>,
+}
+
+impl Config {
+    /// The scopes needed to apply this configuration
+    pub fn scopes(&self) -> Vec<Scope> {
+        let mut scopes = vec![];
+        if self.tags.is_some()
+            || self.language.is_some()
+            || self.title.is_some()
+            || self.category.is_some()
+        {
+            scopes.push(Scope::ChannelManageBroadcast);
+        }
+        if self.rewards.is_some() {
+            scopes.push(Scope::ChannelManageRedemptions);
+        }
+        scopes
+    }
+
+    /// The scopes needed to compare this configuration with the channel
+    ///
+    /// The channel information and tags are public, only the rewards need a scope.
+    pub fn read_scopes(&self) -> Vec<Scope> {
+        if self.rewards.is_some() {
+            vec![Scope::ChannelReadRedemptions]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Lays `over` over this config, its values replace the ones of this config
+    ///
+    /// Tags and rewards are appended, replacing the ones with the same name or
+    /// title, unless `over` lists them in `replace`. Appended tags have to be
+    /// named in the same `config_locale`, as they are all looked up in it.
+    fn layer(self, over: Config) -> Result<Config, FileError> {
+        let replace = over.replace.unwrap_or_default();
+        if let Some(list) = replace
+            .iter()
+            .find(|list| !matches_any!(list, "tags", "rewards"))
+        {
+            return Err(FileError::Replace(list.clone()));
+        }
+        let replaces = |list: &str| replace.iter().any(|r| matches_any!(r, list));
+        let has_tags = |tags: &Option<Vec<String>>| tags.as_ref().map_or(false, |t| !t.is_empty());
+        // the locale belongs to the tags, it is kept with the ones that remain
+        let config_locale = match (has_tags(&self.tags), has_tags(&over.tags)) {
+            (true, true) if !replaces("tags") => {
+                if self.config_locale != over.config_locale {
+                    return Err(FileError::TagLocales(
+                        self.config_locale,
+                        over.config_locale,
+                    ));
+                }
+                over.config_locale
+            }
+            (true, false) => self.config_locale,
+            (_, true) => over.config_locale,
+            (false, false) => over.config_locale.or(self.config_locale),
+        };
+        Ok(Config {
+            extends: None,
+            replace: None,
+            title: over.title.or(self.title),
+            category: over.category.or(self.category),
+            language: over.language.or(self.language),
+            config_locale,
+            tags: append(self.tags, over.tags, replaces("tags"), |a, b| {
+                a.eq_ignore_ascii_case(b)
+            }),
+            notification: over.notification.or(self.notification),
+            rewards: append(self.rewards, over.rewards, replaces("rewards"), |a, b| {
+                a.title().eq_ignore_ascii_case(b.title())
+            }),
+        })
+    }
 }
 
-pub fn valid_extension(file: &PathBuf) -> bool {
+/// `over` appended to `under`, without the entries of `under` that are `same` as one of `over`
+fn append<T>(
+    under: Option<Vec<T>>,
+    over: Option<Vec<T>>,
+    replace: bool,
+    same: impl Fn(&T, &T) -> bool,
+) -> Option<Vec<T>> {
+    match (under, over) {
+        (Some(mut under), Some(over)) if !replace => {
+            under.retain(|entry| !over.iter().any(|o| same(entry, o)));
+            under.extend(over);
+            Some(under)
+        }
+        (under, over) => over.or(under),
+    }
+}
+
+pub fn valid_extension(file: &Path) -> bool {
     if let Some(e) = file.extension() {
-        matches_any!(e, "ini", "toml", "yml", "json")
+        matches_any!(e, "ini", "toml", "yaml", "yml", "json")
     } else {
         false
     }
 }
 
-pub async fn handle_file(
-    client: ApiClient<'_>,
-    file: &PathBuf,
-    noenv: bool,
-) -> Result<(), Box<dyn Error>> {
+/// Reads the config file on its own, without the presets it extends
+pub fn read_config(file: &Path) -> Result<Config, FileError> {
+    let fig = match file.extension() {
+        Some(ext) if matches_any!(ext, "yaml", "yml") => Figment::from(Yaml::file(&file)),
+        Some(ext) if matches_any!(ext, "json") => Figment::from(Json::file(&file)),
+        Some(ext) if matches_any!(ext, "toml") => Figment::from(Toml::file(&file)),
+        Some(ext) if matches_any!(ext, "ini") => {
+            let content = fs::read_to_string(file).map_err(|e| FileError::Read(file.into(), e))?;
+            return ini::from_str(&content).map_err(|e| FileError::Ini(file.into(), e));
+        }
+        Some(ext) => {
+            return Err(FileError::UnsupportedFormat(
+                ext.to_string_lossy().to_string(),
+            ))
+        }
+        None => return Err(FileError::NoExtension),
+    };
+    fig.extract().map_err(|e| FileError::Parse(Box::new(e)))
+}
+
+/// Reads the config file laid over the presets it extends, which are resolved the same way
+pub fn resolve_config(file: &Path) -> Result<Config, FileError> {
+    resolve(file, file, &mut vec![])
+}
+
+/// Resolves the draft of `file` as if it was saved over it, so it can't extend itself
+pub fn resolve_draft(draft: &Path, file: &Path) -> Result<Config, FileError> {
+    resolve(draft, file, &mut vec![])
+}
+
+/// Reads `file` as if it was at `at`, `chain` holds the files currently being resolved, to find
+/// cycles
+fn resolve(file: &Path, at: &Path, chain: &mut Vec<PathBuf>) -> Result<Config, FileError> {
+    let path = at.canonicalize().unwrap_or_else(|_| at.into());
+    if let Some(start) = chain.iter().position(|resolving| *resolving == path) {
+        let name = |file: &PathBuf| {
+            file.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        };
+        let mut cycle: Vec<String> = chain[start..].iter().map(name).collect();
+        cycle.push(name(&path));
+        return Err(FileError::Cycle(cycle));
+    }
+
+    let mut config = read_config(file)?;
+    chain.push(path);
+    let mut resolved = Config::default();
+    for preset in config.extends.take().unwrap_or_default() {
+        let file = find_preset(&preset).map_err(|e| FileError::Extends(preset.clone(), e))?;
+        resolved = resolved.layer(resolve(&file, &file, chain)?)?;
+    }
+    chain.pop();
+    resolved.layer(config)
+}
+
+/// Reads the config file and the presets it extends, with the `TWITCHCTL_DEFAULT_` and `TWITCHCTL_` variables unless `noenv`
+pub fn load_config(file: &Path, noenv: bool) -> Result<Config, FileError> {
     let mut fig = Figment::new();
     if !noenv {
         fig = fig.merge(Env::prefixed("TWITCHCTL_DEFAULT_"));
     }
-    fig = match file.extension() {
-        Some(ext) if matches_any!(ext, "yaml", "yml", "json") => fig.merge(Yaml::file(&file)),
-        Some(ext) if matches_any!(ext, "ini", "toml") => fig.merge(Toml::file(&file)),
-        Some(ext) => exit!(1, "Format not supported: `{}`.", ext.to_string_lossy()),
-        None => exit!(1, "Config file needs an extension defining the format."),
-    };
+    fig = fig.merge(Serialized::defaults(resolve_config(file)?));
     if !noenv {
         fig = fig.merge(Env::prefixed("TWITCHCTL_"));
     }
-    let config: Config = fig
-        .extract()
-        .unwrap_or_else(|e| exit!(1, "Failed to parse configuration: {:?}", e));
-    // To not move config struct
-    let tags = config.tags;
-    let locale = config.config_locale;
-
-    let tag_rq = async {
-        if let Some(tags) = tags {
-            client
-                .replace_stream_tags(
-                    client.get_user_id(),
-                    client
-                        .get_tag_ids_matching(
-                            tags.as_slice(),
-                            match locale.as_ref() {
-                                Some(locale) => locale,
-                                None => "en-us",
-                            },
-                        )
-                        .await
-                        .unwrap_or_else(|e| exit!(1, "Failed to request tags: {:?}", e)),
-                )
-                .await
-                .unwrap_or_else(|e| exit!(1, "Failed to set tags: {:?}", e));
-        }
-    };
-    if config.language.is_some() || config.title.is_some() || config.category.is_some() {
-        let mut builder = ChannelInfoBuilder::default();
-        if let Some(lang) = config.language {
-            builder.language(lang);
-        }
-        if let Some(title) = config.title {
-            builder.title(title);
-        }
-        if let Some(category) = config.category {
-            builder.category(
-                client
-                    .search_category(&category)
-                    .await
-                    .unwrap_or_else(|e| exit!(1, "Failed to request category: {:?}", e))
-                    .unwrap_or_else(|| exit!(1, "Could not find a category for `{}`", category))
-                    .id,
-            );
-        }
-        client
-            .modify_channel_information(client.get_user_id(), builder.build().unwrap())
-            .await
-            .unwrap_or_else(|e| exit!(1, "Failed to set channel information: {:?}", e))
-    }
-    if let Some(_notification) = config.notification {
-        warning!("Setting notification is not yet supported");
-    }
-    if let Some(rewards) = config.rewards {
-        client
-            .get_rewards(client.get_user_id())
-            .await?
-            .iter()
-            .map(|CustomReward { id, .. }| {
-                client.update_custom_reward(
-                    client.get_user_id(),
-                    id,
-                    UpdateCustomRewardBody::builder().is_enabled(false).build(),
-                )
-            })
-            .collect::<JoinAll<_>>()
-            .await;
-
-        rewards
-            .iter()
-            .map(|title| client.find_reward(client.get_user_id(), title))
-            .collect::<JoinAll<_>>()
-            .await
-            .iter()
-            .filter_map(|r| {
-                if let Ok(Some(CustomReward { id, .. })) = r {
-                    Some(client.update_custom_reward(
-                        client.get_user_id(),
-                        id,
-                        UpdateCustomRewardBody::builder().is_enabled(true).build(),
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect::<JoinAll<_>>()
-            .await;
+    fig.extract().map_err(|e| FileError::Parse(Box::new(e)))
+}
+
+/// The config in the format of the extension `format`, like `toml`
+pub fn config_to_string(config: &Config, format: &str) -> Result<String, FileError> {
+    let serialize_error = |e: &dyn Error| FileError::Serialize(e.to_string());
+    if matches_any!(format, "ini") {
+        ini::to_string(config).map_err(FileError::Serialize)
+    } else if matches_any!(format, "toml") {
+        toml::to_string_pretty(config).map_err(|e| serialize_error(&e))
+    } else if matches_any!(format, "yaml", "yml") {
+        serde_yaml::to_string(config).map_err(|e| serialize_error(&e))
+    } else if matches_any!(format, "json") {
+        serde_json::to_string_pretty(config).map_err(|e| serialize_error(&e))
+    } else {
+        Err(FileError::UnsupportedFormat(format.to_string()))
     }
-    tag_rq.await;
+}
+
+/// Writes the config to `file` in the format of its extension
+pub fn write_config(config: &Config, file: &Path) -> Result<(), FileError> {
+    let format = file.extension().ok_or(FileError::NoExtension)?;
+    let content = config_to_string(config, &format.to_string_lossy())?;
+    fs::write(file, content).map_err(|e| FileError::Write(file.into(), e))
+}
 
-    Ok(())
+/// Applies the config file, making only the calls needed to change the channel
+///
+/// The title is rendered with the values given with `--set`.
+pub async fn handle_file(
+    client: ApiClient<'_>,
+    file: &Path,
+    noenv: bool,
+    set: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let config = load_config(file, noenv)?;
+    client.check_scopes(&config.scopes())?;
+    let plan = Plan::with_values(&client, &config, &Values::new(set.to_vec())).await?;
+    if let Some(notice) = plan.notice() {
+        println!("{}", notice);
+    }
+    plan.apply(&client).await
 }
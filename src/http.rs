@@ -0,0 +1,256 @@
+use http::{header::HeaderMap, Method, StatusCode};
+use rand::Rng;
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Semaphore;
+use twitch_api2::{
+    client::{BoxedFuture, Req, Response},
+    twitch_oauth2::oauth2::{url::form_urlencoded, HttpRequest, HttpResponse},
+    HttpClient,
+};
+
+use crate::config::{Endpoints, TWITCH_HELIX_URL, TWITCH_OAUTH_URL};
+
+/// How many requests are sent at the same time
+pub const MAX_IN_FLIGHT: usize = 8;
+/// How often a request is retried when it hit the rate limit or Twitch failed
+const MAX_RETRIES: u32 = 3;
+/// The delay before the first retry, doubled for every further one
+const BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(thiserror::Error, Debug)]
+pub enum HttpError {
+    #[error("{0}")]
+    Backend(Box<dyn Error + Send + Sync>),
+    #[error(transparent)]
+    Http(#[from] http::Error),
+}
+
+/// Sends the http requests of twitchctl
+///
+/// Implement this to send the requests somewhere else than the network,
+/// like a fake Twitch in tests.
+pub trait HttpBackend: Send + Sync {
+    fn send(&self, request: Req) -> BoxedFuture<'_, Result<Response, HttpError>>;
+
+    /// Whether the requests are recorded to or replayed from a cassette
+    ///
+    /// A cassette has to hold every request, so caches are not used with it.
+    fn is_cassette(&self) -> bool {
+        false
+    }
+}
+
+/// The default backend, sending requests with surf
+#[derive(Debug, Default)]
+pub struct SurfBackend(surf::Client);
+
+impl SurfBackend {
+    pub fn new() -> SurfBackend {
+        SurfBackend(surf::Client::new())
+    }
+}
+
+impl HttpBackend for SurfBackend {
+    fn send(&self, request: Req) -> BoxedFuture<'_, Result<Response, HttpError>> {
+        Box::pin(async move {
+            self.0
+                .req(request)
+                .await
+                .map_err(|e| HttpError::Backend(Box::new(e)))
+        })
+    }
+}
+
+/// The points left in helix's rate limit bucket, from the `Ratelimit-*` headers
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    remaining: u64,
+    reset: SystemTime,
+}
+
+impl Bucket {
+    fn from_headers(headers: &HeaderMap) -> Option<Bucket> {
+        let header = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+        Some(Bucket {
+            remaining: header("Ratelimit-Remaining")?,
+            reset: UNIX_EPOCH + Duration::from_secs(header("Ratelimit-Reset")?),
+        })
+    }
+}
+
+/// Spreads the requests of all clones of a [`TwitchHttp`] over the rate limit
+#[derive(Debug)]
+struct RateLimit {
+    in_flight: Semaphore,
+    bucket: Mutex<Option<Bucket>>,
+}
+
+impl RateLimit {
+    /// Waits for the bucket to refill if it is empty
+    async fn wait(&self) {
+        let bucket = *self.bucket.lock().expect("not poisoned");
+        if let Some(Bucket {
+            remaining: 0,
+            reset,
+        }) = bucket
+        {
+            if let Ok(wait) = reset.duration_since(SystemTime::now()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    fn update(&self, headers: &HeaderMap) {
+        if let Some(bucket) = Bucket::from_headers(headers) {
+            *self.bucket.lock().expect("not poisoned") = Some(bucket);
+        }
+    }
+}
+
+/// The delay before the `attempt`th retry, with up to half of it added at random
+///
+/// The jitter keeps the requests sent at once from being retried at once.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BACKOFF * 2u32.pow(attempt - 1);
+    delay + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+}
+
+/// Whether a request is retried after getting a response with `status`
+///
+/// Posts are not retried when Twitch failed, it might have created something already.
+fn should_retry(method: &Method, status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || (status.is_server_error() && method != Method::POST)
+}
+
+fn copy_request(request: &Req) -> Result<Req, HttpError> {
+    let mut builder = http::Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = request.headers().clone();
+    }
+    Ok(builder.body(request.body().clone())?)
+}
+
+/// The http client for Twitch's APIs
+///
+/// Requests to Twitch's Helix and OAuth urls are sent to the configured
+/// [`Endpoints`] instead.
+///
+/// At most [`MAX_IN_FLIGHT`] requests are sent at once. When Helix's rate
+/// limit bucket is empty, requests wait for it to refill, and requests that
+/// still hit the limit or failed on Twitch's side are retried with backoff.
+#[derive(Clone)]
+pub struct TwitchHttp {
+    backend: Arc<dyn HttpBackend>,
+    endpoints: Endpoints,
+    limit: Arc<RateLimit>,
+}
+
+impl TwitchHttp {
+    pub fn new(backend: Arc<dyn HttpBackend>, endpoints: Endpoints) -> TwitchHttp {
+        TwitchHttp {
+            backend,
+            endpoints,
+            limit: Arc::new(RateLimit {
+                in_flight: Semaphore::new(MAX_IN_FLIGHT),
+                bucket: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// A client sending its requests with [`SurfBackend`]
+    pub fn surf(endpoints: Endpoints) -> TwitchHttp {
+        TwitchHttp::new(Arc::new(SurfBackend::new()), endpoints)
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    /// See [`HttpBackend::is_cassette`]
+    pub fn is_cassette(&self) -> bool {
+        self.backend.is_cassette()
+    }
+
+    fn rewrite(&self, url: &str) -> Option<String> {
+        [
+            (TWITCH_HELIX_URL, &self.endpoints.helix_url),
+            (TWITCH_OAUTH_URL, &self.endpoints.oauth_url),
+        ]
+        .iter()
+        .filter(|(twitch, configured)| twitch != configured)
+        .find_map(|(twitch, configured)| {
+            url.strip_prefix(twitch)
+                .map(|path| format!("{}{}", configured, path))
+        })
+    }
+
+    pub async fn send(&self, mut request: Req) -> Result<Response, HttpError> {
+        if let Some(url) = self.rewrite(&request.uri().to_string()) {
+            *request.uri_mut() = url.parse().map_err(http::Error::from)?;
+        }
+        let _permit = self
+            .limit
+            .in_flight
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+
+        let mut attempt = 0;
+        loop {
+            self.limit.wait().await;
+            let response = self.backend.send(copy_request(&request)?).await?;
+            self.limit.update(response.headers());
+            if attempt == MAX_RETRIES || !should_retry(request.method(), response.status()) {
+                return Ok(response);
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+    }
+
+    /// Sends a request made by `twitch_oauth2`
+    pub async fn oauth(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut builder = http::Request::builder()
+            .method(request.method)
+            .uri(request.url.as_str());
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(request.headers);
+        }
+        let response = self.send(builder.body(request.body)?).await?;
+        Ok(HttpResponse {
+            status_code: response.status(),
+            headers: response.headers().clone(),
+            body: response.into_body(),
+        })
+    }
+
+    /// Posts a form to an endpoint of the OAuth url
+    pub async fn post_form(
+        &self,
+        endpoint: &str,
+        form: &[(&str, &str)],
+    ) -> Result<Response, HttpError> {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(form)
+            .finish();
+        let request = http::Request::post(format!("{}/{}", self.endpoints.oauth_url, endpoint))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.into_bytes())?;
+        self.send(request).await
+    }
+}
+
+impl<'a> HttpClient<'a> for TwitchHttp {
+    type Error = HttpError;
+
+    fn req(&'a self, request: Req) -> BoxedFuture<'a, Result<Response, HttpError>> {
+        Box::pin(self.send(request))
+    }
+}
@@ -0,0 +1,425 @@
+//! Reading and writing config files in INI format
+//!
+//! Keys before the first section belong to the config itself, a `[section]`
+//! is a table under its name. A key given more than once and a section given
+//! more than once become lists, so
+//!
+//! ```ini
+//! title = Speedrunning
+//! tags = Speedrun
+//! tags = English
+//!
+//! [rewards]
+//! title = Hydrate
+//! cost = 200
+//! ```
+//!
+//! has two tags and one reward definition. Lines starting with `;` or `#` are
+//! comments. Values are text until deserialized, so `cost = 200` is a number
+//! only because the cost is one. Values with surrounding quotes keep their
+//! whitespace, an empty value without quotes is an empty list.
+
+use serde::{
+    de::{
+        self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+    },
+    forward_to_deserialize_any, Serialize,
+};
+use serde_yaml::{Mapping, Value};
+use std::{fmt, str::FromStr, vec};
+
+/// An error in an INI file, with the line and column it was found at
+#[derive(Debug)]
+pub struct IniError {
+    message: String,
+    position: Option<(usize, usize)>,
+}
+
+impl IniError {
+    fn new(message: impl Into<String>, line: usize, column: usize) -> IniError {
+        IniError {
+            message: message.into(),
+            position: Some((line, column)),
+        }
+    }
+
+    /// The error at `position`, unless it already has one
+    fn at(mut self, position: Option<(usize, usize)>) -> IniError {
+        self.position = self.position.or(position);
+        self
+    }
+}
+
+impl fmt::Display for IniError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{} at line {} column {}", self.message, line, column)
+            }
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for IniError {}
+
+impl de::Error for IniError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        IniError {
+            message: message.to_string(),
+            position: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Text {
+        value: String,
+        quoted: bool,
+        position: (usize, usize),
+    },
+    List(Vec<Node>),
+    Table {
+        entries: Vec<(String, Node)>,
+        position: Option<(usize, usize)>,
+    },
+}
+
+impl Node {
+    fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Node::Text { position, .. } => Some(*position),
+            Node::List(nodes) => nodes.first().and_then(Node::position),
+            Node::Table { position, .. } => *position,
+        }
+    }
+
+    fn text(&self) -> Result<&str, IniError> {
+        match self {
+            Node::Text { value, .. } => Ok(value),
+            Node::List(_) => Err(de::Error::custom("expected a single value, found a list")),
+            Node::Table { .. } => Err(de::Error::custom("expected a value, found a section")),
+        }
+    }
+
+    fn parse<T: FromStr>(&self) -> Result<T, IniError>
+    where
+        T::Err: fmt::Display,
+    {
+        let text = self.text()?;
+        text.parse()
+            .map_err(|e| de::Error::custom(format!("invalid value `{}`: {}", text, e)))
+    }
+}
+
+/// Adds the value to `entries`, making a list of the values if `key` is already there
+fn insert(entries: &mut Vec<(String, Node)>, key: String, node: Node) {
+    match entries.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, Node::List(nodes))) => nodes.push(node),
+        Some((_, existing)) => {
+            let first = std::mem::replace(existing, Node::List(vec![]));
+            *existing = Node::List(vec![first, node]);
+        }
+        None => entries.push((key, node)),
+    }
+}
+
+/// The entries of the last table added under `key`
+fn last_table<'e>(entries: &'e mut [(String, Node)], key: &str) -> &'e mut Vec<(String, Node)> {
+    let node = entries
+        .iter_mut()
+        .rev()
+        .find(|(existing, _)| existing == key)
+        .map(|(_, node)| node);
+    let node = match node {
+        Some(Node::List(nodes)) => nodes.last_mut(),
+        node => node,
+    };
+    match node {
+        Some(Node::Table { entries, .. }) => entries,
+        _ => unreachable!("a section was just added under `{}`", key),
+    }
+}
+
+fn parse(input: &str) -> Result<Node, IniError> {
+    let mut entries = vec![];
+    let mut section: Option<String> = None;
+    for (index, line) in input.lines().enumerate() {
+        let number = index + 1;
+        let indent = line.len() - line.trim_start().len();
+        let column = |byte: usize| line[..byte].chars().count() + 1;
+        let content = line.trim();
+        if content.is_empty() || content.starts_with(';') || content.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = content.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| {
+                    IniError::new("expected `]`", number, column(line.trim_end().len()))
+                })?
+                .trim();
+            if name.is_empty() {
+                return Err(IniError::new(
+                    "expected a section name",
+                    number,
+                    column(indent) + 1,
+                ));
+            }
+            let table = Node::Table {
+                entries: vec![],
+                position: Some((number, column(indent))),
+            };
+            insert(&mut entries, name.to_string(), table);
+            section = Some(name.to_string());
+            continue;
+        }
+
+        let equals = line.find('=').ok_or_else(|| {
+            IniError::new(
+                "expected `=` after the key",
+                number,
+                column(line.trim_end().len()),
+            )
+        })?;
+        let key = line[..equals].trim();
+        if key.is_empty() {
+            return Err(IniError::new(
+                "expected a key before `=`",
+                number,
+                column(indent),
+            ));
+        }
+        let raw = &line[equals + 1..];
+        let start = equals + 1 + raw.len() - raw.trim_start().len();
+        let raw = raw.trim();
+        let (value, quoted) = match raw.strip_prefix('"').and_then(|raw| raw.strip_suffix('"')) {
+            Some(value) => (value, true),
+            None => (raw, false),
+        };
+        let node = Node::Text {
+            value: value.to_string(),
+            quoted,
+            position: (number, column(start)),
+        };
+        let table = match &section {
+            Some(section) => last_table(&mut entries, section),
+            None => &mut entries,
+        };
+        insert(table, key.to_string(), node);
+    }
+    Ok(Node::Table {
+        entries,
+        position: None,
+    })
+}
+
+/// Reads a `T` from INI
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, IniError> {
+    T::deserialize(parse(input)?)
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+                let position = self.position();
+                self.parse()
+                    .and_then(|value| visitor.$visit(value))
+                    .map_err(|e| e.at(position))
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Node {
+    type Error = IniError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        let position = self.position();
+        match self {
+            Node::Text { value, .. } => visitor.visit_string(value),
+            Node::List(nodes) => visitor.visit_seq(Nodes(nodes.into_iter())),
+            Node::Table { entries, .. } => visitor.visit_map(Entries(entries.into_iter(), None)),
+        }
+        .map_err(|e| e.at(position))
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        visitor.visit_some(self)
+    }
+
+    /// A single value is a list of one, an empty value without quotes an empty list
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, IniError> {
+        let position = self.position();
+        let nodes = match self {
+            Node::List(nodes) => nodes,
+            Node::Text {
+                ref value,
+                quoted: false,
+                ..
+            } if value.is_empty() => vec![],
+            node => vec![node],
+        };
+        visitor
+            .visit_seq(Nodes(nodes.into_iter()))
+            .map_err(|e| e.at(position))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, IniError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, IniError> {
+        let position = self.position();
+        visitor
+            .visit_enum(self.text()?.to_string().into_deserializer())
+            .map_err(|e: IniError| e.at(position))
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Nodes(vec::IntoIter<Node>);
+
+impl<'de> SeqAccess<'de> for Nodes {
+    type Error = IniError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, IniError> {
+        self.0.next().map(|node| seed.deserialize(node)).transpose()
+    }
+}
+
+struct Entries(vec::IntoIter<(String, Node)>, Option<Node>);
+
+impl<'de> MapAccess<'de> for Entries {
+    type Error = IniError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, IniError> {
+        match self.0.next() {
+            Some((key, node)) => {
+                self.1 = Some(node);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, IniError> {
+        let node = self.1.take().expect("a key was read before its value");
+        seed.deserialize(node)
+    }
+}
+
+/// Writes `value` as INI, lists as repeated keys and tables as sections
+///
+/// Only keys at the top level can hold tables, a section can't have one.
+pub fn to_string<T: Serialize>(value: &T) -> Result<String, String> {
+    // YAML's mapping keeps the order of the fields
+    let entries = match serde_yaml::to_value(value).map_err(|e| e.to_string())? {
+        Value::Mapping(entries) => entries,
+        _ => return Err("only tables can be written as INI".to_string()),
+    };
+    let mut keys = String::new();
+    let mut sections = String::new();
+    for (key, value) in &entries {
+        let key = name(key)?;
+        match value {
+            Value::Sequence(values) if values.is_empty() => keys.push_str(&format!("{} =\n", key)),
+            Value::Sequence(values) => {
+                for value in values {
+                    match value {
+                        Value::Mapping(table) => write_section(&mut sections, key, table)?,
+                        value => write_key(&mut keys, key, value)?,
+                    }
+                }
+            }
+            Value::Mapping(table) => write_section(&mut sections, key, table)?,
+            value => write_key(&mut keys, key, value)?,
+        }
+    }
+    if !keys.is_empty() && !sections.is_empty() {
+        keys.push('\n');
+    }
+    Ok(keys + sections.trim_end() + if sections.is_empty() { "" } else { "\n" })
+}
+
+fn name(key: &Value) -> Result<&str, String> {
+    key.as_str()
+        .ok_or_else(|| "only text can be a key in INI".to_string())
+}
+
+fn write_section(out: &mut String, section: &str, table: &Mapping) -> Result<(), String> {
+    out.push_str(&format!("[{}]\n", section));
+    for (key, value) in table {
+        let key = name(key)?;
+        match value {
+            Value::Sequence(values) => {
+                for value in values {
+                    write_key(out, key, value)?;
+                }
+            }
+            value => write_key(out, key, value)?,
+        }
+    }
+    out.push('\n');
+    Ok(())
+}
+
+fn write_key(out: &mut String, key: &str, value: &Value) -> Result<(), String> {
+    let value = match value {
+        Value::Null => return Ok(()),
+        Value::String(text) if text.contains(|c| c == '\n' || c == '\r') => {
+            return Err(format!(
+                "the value of `{}` has a line break, INI can't hold it",
+                key
+            ))
+        }
+        Value::String(text) if text.is_empty() || text.trim() != text || text.starts_with('"') => {
+            format!("\"{}\"", text)
+        }
+        Value::String(text) => text.clone(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::Sequence(_) | Value::Mapping(_) => {
+            return Err(format!("`{}` is nested too deep to be written as INI", key))
+        }
+    };
+    out.push_str(&format!("{} = {}\n", key, value));
+    Ok(())
+}
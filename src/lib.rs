@@ -0,0 +1,28 @@
+//! A sane Twitch command line interface, as a library
+//!
+//! The `twitchctl` binary is a thin wrapper around this crate. Everything it
+//! does, like applying config files and presets or managing tags and rewards,
+//! can be used from other tools through [`ApiClient`] and the command modules,
+//! which return errors instead of exiting the process.
+
+pub mod api;
+pub mod auth;
+pub mod cache;
+pub mod cassette;
+pub mod cli;
+pub mod config;
+pub mod credentials;
+pub mod file;
+pub mod http;
+pub mod ini;
+pub mod plan;
+pub mod preset;
+pub mod rewards;
+pub mod snapshot;
+pub mod tags;
+pub mod template;
+
+#[macro_use]
+mod macros;
+
+pub use api::{ApiClient, ApiError};
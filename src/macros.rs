@@ -1,11 +1,3 @@
-#[macro_export]
-macro_rules! exit {
-    ($ec:expr, $($message:expr), +) => {
-        {eprintln!($($message), +);
-        std::process::exit($ec);}
-    };
-}
-
 #[macro_export]
 macro_rules! matches_any {
     ($value:expr, $first:expr) => {
@@ -1,27 +1,93 @@
 use structopt::StructOpt;
 
-mod api;
-mod cli;
-mod config;
-mod file;
-mod preset;
-mod rewards;
-mod tags;
-
-#[macro_use]
-mod macros;
-
-use api::ApiClient;
-use cli::{Category, CliOptions};
-use config::load_env;
-use file::handle_file;
-use preset::handle_preset;
-use tags::tags;
-use rewards::rewards;
+use std::{error::Error, path::Path, sync::Arc};
+use twitchctl::{
+    auth::auth,
+    cache::TagCache,
+    cassette::{Recorder, Replayer},
+    cli::{Category, CliOptions},
+    config::{
+        load_app_env, load_endpoints, load_env, load_profile, load_replay_app_env, load_replay_env,
+        ConfigError, DEFAULT_LOCALE,
+    },
+    file::handle_file,
+    http::{SurfBackend, TwitchHttp},
+    plan::{handle_apply, handle_plan},
+    preset::{handle_preset, preset, PresetError},
+    rewards::rewards,
+    snapshot::handle_snapshot,
+    tags::{list_cached, tags},
+    ApiClient, ApiError,
+};
+
+macro_rules! exit {
+    ($ec:expr, $($message:expr), +) => {
+        {eprintln!($($message), +);
+        std::process::exit($ec);}
+    };
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let CliOptions { category } = CliOptions::from_args();
+async fn main() {
+    if let Err(e) = run().await {
+        exit!(exit_code(e.as_ref()), "{}", e);
+    }
+}
+
+/// The exit code for the category of the error, see [`ApiError::exit_code`]
+fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    if let Some(e) = error.downcast_ref::<ApiError>() {
+        e.exit_code()
+    } else if matches!(
+        error.downcast_ref::<ConfigError>(),
+        Some(ConfigError::NoToken)
+            | Some(ConfigError::NoProfileToken(_))
+            | Some(ConfigError::NoClientCredentials)
+    ) {
+        3
+    } else {
+        1
+    }
+}
+
+/// Creates the client with the token of the profile, or an app access token with `app_token`
+///
+/// The requests are recorded to the cassette `record` or answered from the cassette `replay`.
+async fn connect(
+    profile: Option<&str>,
+    app_token: bool,
+    record: Option<&Path>,
+    replay: Option<&Path>,
+) -> Result<ApiClient<'static>, Box<dyn Error>> {
+    let endpoints = load_endpoints(profile)?;
+    let http = match (record, replay) {
+        (_, Some(cassette)) => TwitchHttp::new(Arc::new(Replayer::open(cassette)?), endpoints),
+        (Some(cassette), None) => TwitchHttp::new(
+            Arc::new(Recorder::new(Arc::new(SurfBackend::new()), cassette)),
+            endpoints,
+        ),
+        (None, None) => TwitchHttp::surf(endpoints),
+    };
+    Ok(match (app_token, replay.is_some()) {
+        (true, false) => ApiClient::with_app_http(&load_app_env(profile)?, http).await?,
+        (false, false) => ApiClient::with_http(&load_env(profile)?, http).await?,
+        (true, true) => ApiClient::with_app_http(&load_replay_app_env(profile)?, http).await?,
+        (false, true) => ApiClient::with_http(&load_replay_env(profile)?, http).await?,
+    })
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    // make `.env` available to structopt's env fallbacks
+    dotenv::dotenv().ok();
+    let CliOptions {
+        profile,
+        app_token,
+        refresh,
+        record,
+        replay,
+        dry_run,
+        category,
+    } = CliOptions::from_args();
 
     if let Category::Completions { shell, target_dir } = &category {
         if !target_dir.exists() {
@@ -35,13 +101,59 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if let Category::Auth { options } = category {
+        return auth(profile.as_deref(), options.subcommand).await;
+    }
+
+    match category {
+        Category::Preset {
+            subcommand: Some(subcommand),
+            ..
+        } if !subcommand.needs_twitch() => return preset(None, subcommand).await,
+        Category::Preset { query: None, .. } => return Err(PresetError::NoQuery.into()),
+        _ => {}
+    }
+
     // check token after cli and completions are done
     // otherwise the tool crashes when you try to call it with -h
-    let env = load_env();
-    let client = ApiClient::new(&env.token).await?;
+    let stored = load_profile(profile.as_deref())?;
+    // a cassette has to hold the requests for the tags, so it can't fall back to the cache
+    let tag_cache =
+        TagCache::new(stored.cache_ttl(), refresh).filter(|_| record.is_none() && replay.is_none());
+    let client = match connect(
+        profile.as_deref(),
+        app_token,
+        record.as_deref(),
+        replay.as_deref(),
+    )
+    .await
+    {
+        Ok(client) => client.with_tag_cache(tag_cache).with_dry_run(dry_run),
+        Err(e) if matches!(e.downcast_ref(), Some(ApiError::Network(_))) => {
+            // the tag catalogue can still be listed from the cache
+            if let (Category::Tags { options }, Some(cache)) = (category, tag_cache) {
+                let locale = options
+                    .locale
+                    .or(stored.locale)
+                    .unwrap_or_else(|| DEFAULT_LOCALE.to_string());
+                let helix_url = load_endpoints(profile.as_deref())?.helix_url;
+                if list_cached(&cache, &helix_url, &locale, options.subcommand)? {
+                    return Ok(());
+                }
+            }
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    client.check_scopes(&category.scopes())?;
 
     match category {
-        Category::Tags { options } => tags(client, &options.locale, options.subcommand).await,
+        Category::Tags { options } => {
+            let locale = options
+                .locale
+                .unwrap_or_else(|| client.locale().to_string());
+            tags(client, &locale, options.subcommand).await?
+        }
         Category::Search {
             category,
             max_results,
@@ -51,12 +163,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 client.search_categories(&category, max_results).await?
             );
         }
-        Category::File { file, noenv } => handle_file(client, &file, noenv).await?,
-        Category::Preset { query, noenv } => handle_preset(client, &query, noenv).await?,
-        Category::Completions { .. } => {
+        Category::File {
+            file,
+            noenv,
+            template,
+        } => handle_file(client, &file, noenv, &template.set).await?,
+        Category::Plan {
+            file,
+            noenv,
+            template,
+        } => handle_plan(client, &file, noenv, &template.set).await?,
+        Category::Apply {
+            file,
+            noenv,
+            template,
+        } => handle_apply(client, &file, noenv, &template.set).await?,
+        Category::Snapshot {
+            output,
+            config_locale,
+        } => handle_snapshot(client, output.as_deref(), config_locale).await?,
+        Category::Preset {
+            subcommand: Some(subcommand),
+            ..
+        } => preset(Some(client), subcommand).await?,
+        Category::Preset {
+            query,
+            noenv,
+            template,
+            ..
+        } => {
+            let query = query.expect("checked before connecting");
+            handle_preset(client, &query, noenv, &template.set).await?
+        }
+        Category::Completions { .. } | Category::Auth { .. } => {
             unreachable!("already handled above!")
         }
-        Category::Reward { options } => rewards(client, options.subcommand).await,
+        Category::Reward { options } => rewards(client, options.subcommand).await?,
     }
 
     Ok(())
@@ -0,0 +1,570 @@
+use ansi_term::Colour::{self, Cyan, Green, Red, Yellow};
+use std::{error::Error, fmt::Display, path::Path};
+use twitch_api2::{
+    helix::{
+        points::{CreateCustomRewardBody, CustomReward, UpdateCustomRewardBody},
+        tags::{AutoGenerated, TwitchTag},
+    },
+    types::{CategoryId, UserId},
+};
+
+use crate::{
+    api::{match_reward, ApiError, ChannelInfoBuilder},
+    config::DEFAULT_LOCALE,
+    file::{load_config, Config, FileError},
+    rewards::{RewardConfig, RewardOption},
+    template::{render, Counters, Values},
+    warning, ApiClient,
+};
+
+/// The most characters Twitch allows in the title
+pub const TITLE_MAX_LENGTH: usize = 140;
+
+/// The most characters Twitch allows in the go-live notification
+pub const NOTIFICATION_MAX_LENGTH: usize = 140;
+
+/// A value that is changed by applying a config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    /// `None` when the value stays the same
+    fn new(from: T, to: T) -> Option<Change<T>> {
+        if from == to {
+            None
+        } else {
+            Some(Change { from, to })
+        }
+    }
+}
+
+/// A category, equal to another one with the same id
+#[derive(Debug, Clone)]
+pub struct ChannelCategory {
+    pub id: CategoryId,
+    pub name: String,
+}
+
+impl PartialEq for ChannelCategory {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Display for ChannelCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// A reward that differs from its definition in the config
+#[derive(Debug, Clone)]
+pub struct RewardUpdate {
+    /// the reward before the update
+    pub reward: CustomReward,
+    pub body: UpdateCustomRewardBody,
+    /// the differences, like `cost 100 -> 200`
+    pub changes: Vec<String>,
+}
+
+/// The changes applying a config makes to the channel
+///
+/// Only what the config sets and differs from the channel is changed, so
+/// applying it again makes no calls at all.
+#[derive(Debug, Default)]
+pub struct Plan {
+    broadcaster: UserId,
+    /// the locale tag names are shown in
+    locale: String,
+    pub title: Option<Change<String>>,
+    /// the go-live notification, it can't be read or set with Twitch's API
+    pub notification: Option<String>,
+    pub language: Option<Change<String>>,
+    pub category: Option<Change<ChannelCategory>>,
+    /// the manual tags, automatic tags can not be changed
+    pub tags: Option<Change<Vec<TwitchTag>>>,
+    /// the tags of the config no tag is named like, they are left out of `tags`
+    pub unknown_tags: Vec<String>,
+    pub enable: Vec<CustomReward>,
+    pub disable: Vec<CustomReward>,
+    /// the rewards defined in the config that don't exist yet
+    pub create: Vec<CreateCustomRewardBody>,
+    pub update: Vec<RewardUpdate>,
+    /// the counters of the title, incremented once it is applied
+    counters: Option<(Counters, Vec<String>)>,
+}
+
+/// The name of the tag in `locale`, or else in English, `None` if it has neither
+pub(crate) fn tag_name<'t>(tag: &'t TwitchTag, locale: &str) -> Option<&'t String> {
+    tag.localization_names
+        .get(locale)
+        .or_else(|| tag.localization_names.get(DEFAULT_LOCALE))
+}
+
+/// The tags of `tags` missing in `other`
+fn missing<'t>(tags: &'t [TwitchTag], other: &[TwitchTag]) -> Vec<&'t TwitchTag> {
+    tags.iter()
+        .filter(|tag| !other.iter().any(|o| o.id == tag.id))
+        .collect()
+}
+
+impl Plan {
+    /// Compares the channel of the client's user with `config`
+    ///
+    /// Only what `config` sets is fetched, the category and tags are looked up
+    /// before anything is changed.
+    pub async fn new(client: &ApiClient<'_>, config: &Config) -> Result<Plan, Box<dyn Error>> {
+        Plan::with_values(client, config, &Values::new(vec![])).await
+    }
+
+    /// Compares the channel with `config`, its title rendered with `values`
+    pub async fn with_values(
+        client: &ApiClient<'_>,
+        config: &Config,
+        values: &Values,
+    ) -> Result<Plan, Box<dyn Error>> {
+        let broadcaster = client.get_user_id()?.clone();
+        let locale = config
+            .config_locale
+            .clone()
+            .unwrap_or_else(|| client.locale().to_string());
+        let mut plan = Plan::default();
+
+        // checked before anything is fetched, so nothing is changed with an invalid config
+        if let Some(notification) = &config.notification {
+            let length = notification.chars().count();
+            if length > NOTIFICATION_MAX_LENGTH {
+                return Err(FileError::NotificationTooLong(length, NOTIFICATION_MAX_LENGTH).into());
+            }
+            plan.notification = Some(notification.clone());
+        }
+        let title = match &config.title {
+            Some(template) => {
+                let rendered = render(template, values)?;
+                let length = rendered.text.chars().count();
+                if length > TITLE_MAX_LENGTH {
+                    return Err(
+                        FileError::TitleTooLong(rendered.text, length, TITLE_MAX_LENGTH).into(),
+                    );
+                }
+                if let (Some(counters), false) = (values.counters(), rendered.counters.is_empty()) {
+                    plan.counters = Some((counters.clone(), rendered.counters));
+                }
+                Some(rendered.text)
+            }
+            None => None,
+        };
+
+        if config.title.is_some() || config.language.is_some() || config.category.is_some() {
+            let channel = client
+                .get_channel_information(&broadcaster)
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("channel {}", broadcaster)))?;
+            if let Some(title) = title {
+                plan.title = Change::new(channel.title, title);
+            }
+            if let Some(language) = &config.language {
+                plan.language = Change::new(channel.broadcaster_language, language.clone());
+            }
+            if let Some(category) = &config.category {
+                let found = client
+                    .search_category(category)
+                    .await?
+                    .ok_or_else(|| FileError::NoCategory(category.clone()))?;
+                plan.category = Change::new(
+                    ChannelCategory {
+                        id: channel.game_id,
+                        name: channel.game_name,
+                    },
+                    ChannelCategory {
+                        id: found.id,
+                        name: found.name,
+                    },
+                );
+            }
+        }
+
+        if let Some(tags) = &config.tags {
+            let to = client.get_tags_matching(tags, &locale).await?;
+            let is_named = |tag: &TwitchTag, name: &str| {
+                [locale.as_str(), DEFAULT_LOCALE].iter().any(|locale| {
+                    tag.localization_names
+                        .get(*locale)
+                        .map_or(false, |tag| tag.eq_ignore_ascii_case(name))
+                })
+            };
+            for name in tags {
+                if !to.iter().any(|tag| is_named(tag, name)) {
+                    warning!("No tag is named `{}`, it is left out.", name);
+                    plan.unknown_tags.push(name.clone());
+                }
+            }
+            let from: Vec<_> = client
+                .get_stream_tags(&broadcaster)
+                .await?
+                .into_iter()
+                .filter(|tag| tag.is_auto == AutoGenerated::False)
+                .collect();
+            if !missing(&from, &to).is_empty() || !missing(&to, &from).is_empty() {
+                plan.tags = Some(Change { from, to });
+            }
+        }
+
+        if let Some(rewards) = &config.rewards {
+            let current = client.get_rewards(&broadcaster).await?;
+            let mut enabled = vec![];
+            for reward in rewards {
+                match reward {
+                    RewardConfig::Title(title) => match match_reward(&current, title) {
+                        Some(reward) => enabled.push(reward.id.clone()),
+                        None => warning!("No reward matches `{}`, it is left out.", title),
+                    },
+                    // defined rewards are matched exactly, to not update another one
+                    RewardConfig::Definition(definition) => match current
+                        .iter()
+                        .find(|reward| reward.title.eq_ignore_ascii_case(&definition.title))
+                    {
+                        Some(reward) => {
+                            enabled.push(reward.id.clone());
+                            if let Some((body, changes)) = definition.update_body(reward) {
+                                plan.update.push(RewardUpdate {
+                                    reward: reward.clone(),
+                                    body,
+                                    changes,
+                                });
+                            }
+                        }
+                        None => plan.create.push(definition.create_body()?),
+                    },
+                }
+            }
+            for reward in current {
+                // updates of defined rewards enable them as well
+                let updated = plan
+                    .update
+                    .iter()
+                    .any(|update| update.reward.id == reward.id);
+                match (enabled.contains(&reward.id), reward.is_enabled) {
+                    (true, false) if !updated => plan.enable.push(reward),
+                    (false, true) => plan.disable.push(reward),
+                    _ => {}
+                }
+            }
+        }
+
+        plan.broadcaster = broadcaster;
+        plan.locale = locale;
+        Ok(plan)
+    }
+
+    /// Whether applying the config changes nothing
+    ///
+    /// The notification is not counted, it can't be set by applying the config.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.language.is_none()
+            && self.category.is_none()
+            && self.tags.is_none()
+            && self.enable.is_empty()
+            && self.disable.is_empty()
+            && self.create.is_empty()
+            && self.update.is_empty()
+    }
+
+    /// The changes as a diff, one line per change
+    ///
+    /// The notice about the notification follows the title, it is shown even if nothing changes.
+    /// With `color`, changed values are yellow, added ones green, removed ones red and the notice
+    /// cyan.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |colour: Colour, line: String| {
+            if color {
+                colour.paint(line).to_string()
+            } else {
+                line
+            }
+        };
+        let mut lines = vec![];
+        if let Some(Change { from, to }) = &self.title {
+            lines.push(paint(Yellow, format!("~ title: {:?} -> {:?}", from, to)));
+        }
+        if let Some(notice) = self.notice() {
+            lines.push(paint(Cyan, format!("! {}", notice)));
+        }
+        if let Some(Change { from, to }) = &self.category {
+            lines.push(paint(Yellow, format!("~ category: {} -> {}", from, to)));
+        }
+        if let Some(Change { from, to }) = &self.language {
+            lines.push(paint(Yellow, format!("~ language: {} -> {}", from, to)));
+        }
+        if let Some(Change { from, to }) = &self.tags {
+            for tag in missing(to, from) {
+                let name = tag_name(tag, &self.locale).unwrap_or(&tag.id);
+                let line = format!("+ tag {}", name);
+                lines.push(paint(Green, line));
+            }
+            for tag in missing(from, to) {
+                let name = tag_name(tag, &self.locale).unwrap_or(&tag.id);
+                let line = format!("- tag {}", name);
+                lines.push(paint(Red, line));
+            }
+        }
+        for reward in &self.enable {
+            lines.push(paint(Green, format!("+ reward {}", reward.title)));
+        }
+        for reward in &self.disable {
+            lines.push(paint(Red, format!("- reward {}", reward.title)));
+        }
+        for reward in &self.create {
+            lines.push(paint(Green, format!("+ reward {} (new)", reward.title)));
+        }
+        for RewardUpdate {
+            reward, changes, ..
+        } in &self.update
+        {
+            let line = format!("~ reward {}: {}", reward.title, changes.join(", "));
+            lines.push(paint(Yellow, line));
+        }
+        lines.join("\n")
+    }
+
+    /// The reminder to set the notification of the config in the dashboard
+    pub fn notice(&self) -> Option<String> {
+        self.notification.as_ref().map(|notification| {
+            format!(
+                "Twitch's API can not set the go-live notification, set it to {:?} in the dashboard.",
+                notification
+            )
+        })
+    }
+
+    /// Prints the changes, colored if stdout is a terminal
+    pub fn print(&self) {
+        if self.is_empty() {
+            println!("No changes, the channel matches the config.");
+        }
+        let rendered = self.render(atty::is(atty::Stream::Stdout));
+        if !rendered.is_empty() {
+            println!("{}", rendered);
+        }
+    }
+
+    /// The calls for the changes, in the order they are made
+    fn steps(&self) -> Vec<Step<'_>> {
+        let mut steps = vec![];
+        if self.title.is_some() || self.language.is_some() || self.category.is_some() {
+            steps.push(Step::ChannelInfo);
+        }
+        if let Some(tags) = &self.tags {
+            steps.push(Step::Tags(tags));
+        }
+        steps.extend(self.create.iter().map(Step::Create));
+        steps.extend(self.update.iter().map(Step::Update));
+        steps.extend(self.enable.iter().map(Step::Enable));
+        steps.extend(self.disable.iter().map(Step::Disable));
+        steps
+    }
+
+    /// Makes the call of the step, returns the reward it created
+    async fn run(
+        &self,
+        client: &ApiClient<'_>,
+        step: &Step<'_>,
+    ) -> Result<Option<CustomReward>, ApiError> {
+        let broadcaster = &self.broadcaster;
+        match step {
+            Step::ChannelInfo => {
+                let mut builder = ChannelInfoBuilder::default();
+                if let Some(Change { to, .. }) = &self.title {
+                    builder.title(to.clone());
+                }
+                if let Some(Change { to, .. }) = &self.language {
+                    builder.language(to.clone());
+                }
+                if let Some(Change { to, .. }) = &self.category {
+                    builder.category(to.id.clone());
+                }
+                client
+                    .modify_channel_information(broadcaster, builder.build().unwrap())
+                    .await?;
+            }
+            Step::Tags(Change { to, .. }) => {
+                client
+                    .replace_stream_tags(broadcaster, to.iter().map(|tag| tag.id.clone()).collect())
+                    .await?;
+            }
+            Step::Enable(reward) => {
+                client
+                    .update_custom_reward(broadcaster, &reward.id, toggle(true))
+                    .await?
+            }
+            Step::Disable(reward) => {
+                client
+                    .update_custom_reward(broadcaster, &reward.id, toggle(false))
+                    .await?
+            }
+            Step::Update(update) => {
+                client
+                    .update_custom_reward(broadcaster, &update.reward.id, update.body.clone())
+                    .await?
+            }
+            Step::Create(body) => {
+                return client
+                    .create_custom_reward(broadcaster, (*body).clone())
+                    .await
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sets back what the step changed, `created` is the reward it created
+    async fn undo(
+        &self,
+        client: &ApiClient<'_>,
+        step: &Step<'_>,
+        created: Option<&CustomReward>,
+    ) -> Result<(), ApiError> {
+        let broadcaster = &self.broadcaster;
+        match step {
+            Step::ChannelInfo => {
+                let mut builder = ChannelInfoBuilder::default();
+                if let Some(Change { from, .. }) = &self.title {
+                    builder.title(from.clone());
+                }
+                if let Some(Change { from, .. }) = &self.language {
+                    builder.language(from.clone());
+                }
+                if let Some(Change { from, .. }) = &self.category {
+                    builder.category(from.id.clone());
+                }
+                client
+                    .modify_channel_information(broadcaster, builder.build().unwrap())
+                    .await
+            }
+            Step::Tags(Change { from, .. }) => client
+                .replace_stream_tags(broadcaster, from.iter().map(|tag| tag.id.clone()).collect())
+                .await
+                .map(|_| ()),
+            Step::Enable(reward) => {
+                client
+                    .update_custom_reward(broadcaster, &reward.id, toggle(false))
+                    .await
+            }
+            Step::Disable(reward) => {
+                client
+                    .update_custom_reward(broadcaster, &reward.id, toggle(true))
+                    .await
+            }
+            Step::Update(RewardUpdate { reward, .. }) => {
+                let body = RewardOption::from(reward).into();
+                client
+                    .update_custom_reward(broadcaster, &reward.id, body)
+                    .await
+            }
+            Step::Create(_) => match created {
+                Some(reward) => client.delete_custom_reward(broadcaster, &reward.id).await,
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Makes the calls for the changes, one after the other
+    ///
+    /// The plan holds the state of everything it changes. When a call fails,
+    /// the changes already made are set back to it, in reverse order, and the
+    /// error lists what was restored.
+    pub async fn apply(&self, client: &ApiClient<'_>) -> Result<(), Box<dyn Error>> {
+        let mut done = vec![];
+        for step in self.steps() {
+            match self.run(client, &step).await {
+                Ok(created) => done.push((step, created)),
+                Err(e) => {
+                    let failed = format!("{} failed: {}", step, e);
+                    let mut restored = vec![];
+                    let mut unrestored = vec![];
+                    for (step, created) in done.iter().rev() {
+                        match self.undo(client, step, created.as_ref()).await {
+                            Ok(()) => restored.push(step.to_string()),
+                            Err(e) => unrestored.push(format!("{}: {}", step, e)),
+                        }
+                    }
+                    return Err(FileError::RolledBack(failed, restored, unrestored).into());
+                }
+            }
+        }
+        if let (Some((counters, names)), Some(_), false) =
+            (&self.counters, &self.title, client.is_dry_run())
+        {
+            if let Err(e) = counters.increment(names) {
+                warning!(
+                    "The title was applied, but its counters not counted up: {}",
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A call made by [`Plan::apply`]
+enum Step<'p> {
+    ChannelInfo,
+    Tags(&'p Change<Vec<TwitchTag>>),
+    Enable(&'p CustomReward),
+    Disable(&'p CustomReward),
+    Update(&'p RewardUpdate),
+    Create(&'p CreateCustomRewardBody),
+}
+
+impl Display for Step<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::ChannelInfo => write!(f, "changing the channel information"),
+            Step::Tags(_) => write!(f, "setting tags"),
+            Step::Enable(reward) => write!(f, "enabling reward `{}`", reward.title),
+            Step::Disable(reward) => write!(f, "disabling reward `{}`", reward.title),
+            Step::Update(update) => write!(f, "updating reward `{}`", update.reward.title),
+            Step::Create(body) => write!(f, "creating reward `{}`", body.title),
+        }
+    }
+}
+
+fn toggle(enabled: bool) -> UpdateCustomRewardBody {
+    UpdateCustomRewardBody::builder()
+        .is_enabled(enabled)
+        .build()
+}
+
+/// Prints what applying the config file would change
+///
+/// The title is rendered with the values given with `--set`.
+pub async fn handle_plan(
+    client: ApiClient<'_>,
+    file: &Path,
+    noenv: bool,
+    set: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let config = load_config(file, noenv)?;
+    client.check_scopes(&config.read_scopes())?;
+    Plan::with_values(&client, &config, &Values::new(set.to_vec()))
+        .await?
+        .print();
+    Ok(())
+}
+
+/// Prints what applying the config file changes and applies it
+pub async fn handle_apply(
+    client: ApiClient<'_>,
+    file: &Path,
+    noenv: bool,
+    set: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let config = load_config(file, noenv)?;
+    client.check_scopes(&config.scopes())?;
+    let plan = Plan::with_values(&client, &config, &Values::new(set.to_vec())).await?;
+    plan.print();
+    plan.apply(&client).await
+}
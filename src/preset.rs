@@ -1,36 +1,196 @@
-use dirs;
-use std::{error::Error, fs, path::PathBuf};
+use ansi_term::Colour::{Blue, Green, Purple, Yellow};
+use ansi_term::Style;
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+use structopt::StructOpt;
+use twitch_api2::twitch_oauth2::Scope;
 
 use crate::{
     api::ApiClient,
-    exit,
-    file::{handle_file, valid_extension},
+    config::{config_dir, ConfigError},
+    file::{
+        config_to_string, handle_file, read_config, resolve_config, resolve_draft, valid_extension,
+        write_config, Config,
+    },
+    matches_any,
+    rewards::RewardConfig,
+    snapshot::snapshot,
+    warning,
 };
 
-pub async fn handle_preset(
-    client: ApiClient<'_>,
-    query: &str,
-    noenv: bool,
-) -> Result<(), Box<dyn Error>> {
-    let mut config_dir = dirs::config_dir().unwrap_or_else(|| {
-        exit!(
-            1,
-            "Could not find the config Home. Maybe set XDG_CONFIG_HOME"
-        )
-    });
-    config_dir.push("twitchctl/presets");
-    if !config_dir.is_dir() {
-        if fs::create_dir_all(&config_dir).is_err() {
-            exit!(
-                1,
-                "Unable to create preset directory at `{}`",
-                config_dir.display()
-            );
+#[derive(thiserror::Error, Debug)]
+pub enum PresetError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("Unable to create preset directory at `{}`", .0.display())]
+    CreateDir(PathBuf),
+    #[error("Unable to read preset directory at `{}`", .0.display())]
+    ReadDir(PathBuf),
+    #[error("Name the preset to apply or a subcommand.")]
+    NoQuery,
+    #[error("No matching presets found.")]
+    NoMatch,
+    #[error("There where multiple files matching the query:\n{}", .0.join("\n"))]
+    MultipleMatches(Vec<String>),
+    #[error("The preset `{0}` already exists.")]
+    Exists(String),
+    #[error("`{0}` is not a valid preset name, it can't be a path.")]
+    InvalidName(String),
+    #[error(
+        "`{0}` is the name of a preset subcommand, the preset could only be applied with \
+        `twitchctl preset -- {0}`. Choose another name."
+    )]
+    SubcommandName(String),
+    #[error("`{0}` only matches `{1}` fuzzily, name the preset exactly to remove it.")]
+    NotExact(String, String),
+    #[error("Found the argument `{0}` which wasn't expected.")]
+    Unexpected(String),
+    #[error("`new --from-channel` needs a connection to Twitch.")]
+    NotConnected,
+    #[error("Could not run the editor `{0}`: {1}")]
+    Editor(String, String),
+    #[error("The preset was not saved, {0}\nThe edited version is kept in `{}`.", .1.display())]
+    Invalid(String, PathBuf),
+}
+
+/// The names of the subcommands, presets named like them are shadowed
+const SUBCOMMANDS: &[&str] = &["list", "show", "new", "edit", "rm", "mv", "help"];
+
+#[derive(Debug, StructOpt)]
+pub enum PresetSubcommand {
+    /// lists the presets with their title and category
+    List {
+        /// string for fuzzy filtering of presets
+        filter: Option<String>,
+    },
+    /// prints a preset, highlighted in a terminal
+    Show {
+        /// print the preset with the presets it extends merged in
+        ///
+        /// Environment variables are not applied.
+        #[structopt(long)]
+        resolved: bool,
+        query: String,
+    },
+    /// creates a preset from flags or the current channel
+    New {
+        /// the name of the preset, TOML unless it has another extension
+        name: String,
+        /// start with the title, category, language, tags and rewards of the channel
+        #[structopt(long)]
+        from_channel: bool,
+        #[structopt(flatten)]
+        preset: PresetOption,
+    },
+    /// opens a preset in `$VISUAL` or `$EDITOR` and saves it if it is valid
+    Edit { query: String },
+    /// removes a preset
+    ///
+    /// A preset only matching the query fuzzily is removed once confirmed.
+    Rm { query: String },
+    /// renames a preset
+    ///
+    /// If the new name has the extension of another format, the preset is converted to it.
+    Mv {
+        query: String,
+        /// the new name, with the extension of the preset unless it has one
+        name: String,
+    },
+    /// arguments left over after the preset to apply
+    ///
+    /// Without it clap refuses queries resembling a subcommand, even after `--`,
+    /// suggesting the subcommand instead.
+    #[structopt(external_subcommand)]
+    Unexpected(Vec<String>),
+}
+
+impl PresetSubcommand {
+    /// The scopes needed to run this command
+    pub fn scopes(&self) -> Vec<Scope> {
+        if self.needs_twitch() {
+            vec![Scope::ChannelReadRedemptions]
+        } else {
+            vec![]
         }
     }
 
-    let files: Vec<PathBuf> = fs::read_dir(&config_dir)
-        .unwrap_or_else(|_| exit!(1, "Unable to read preset directory at `{}`", config_dir.display()))
+    /// Whether the command reads from Twitch, only `new --from-channel` does
+    pub fn needs_twitch(&self) -> bool {
+        matches!(
+            self,
+            PresetSubcommand::New {
+                from_channel: true,
+                ..
+            }
+        )
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PresetOption {
+    /// the title of the stream
+    #[structopt(long)]
+    title: Option<String>,
+    /// the category of the stream
+    #[structopt(long)]
+    category: Option<String>,
+    /// the language of the stream
+    #[structopt(long)]
+    language: Option<String>,
+    /// a tag of the stream, can be given multiple times
+    #[structopt(long = "tag", number_of_values = 1)]
+    tags: Vec<String>,
+    /// a reward to enable, can be given multiple times
+    #[structopt(long = "reward", number_of_values = 1)]
+    rewards: Vec<String>,
+    /// a preset to extend, can be given multiple times
+    #[structopt(long = "extends", number_of_values = 1)]
+    extends: Vec<String>,
+}
+
+impl PresetOption {
+    /// Sets the given values on the config
+    fn apply_to(self, config: &mut Config) {
+        let list = |values: Vec<String>| Some(values).filter(|values| !values.is_empty());
+        config.title = self.title.or_else(|| config.title.take());
+        config.category = self.category.or_else(|| config.category.take());
+        config.language = self.language.or_else(|| config.language.take());
+        config.tags = list(self.tags).or_else(|| config.tags.take());
+        config.rewards = list(self.rewards)
+            .map(|rewards| rewards.into_iter().map(RewardConfig::Title).collect())
+            .or_else(|| config.rewards.take());
+        config.extends = list(self.extends).or_else(|| config.extends.take());
+    }
+}
+
+/// The folder the presets are stored in, created if it is missing
+pub fn presets_dir() -> Result<PathBuf, PresetError> {
+    let mut config_dir = config_dir().ok_or(ConfigError::NoConfigDir)?;
+    config_dir.push("presets");
+    if !config_dir.is_dir() && fs::create_dir_all(&config_dir).is_err() {
+        return Err(PresetError::CreateDir(config_dir));
+    }
+    Ok(config_dir)
+}
+
+fn file_name(file: &Path) -> String {
+    file.file_name()
+        .expect("all files have filenames")
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The presets matching the query fuzzily, sorted by name
+fn presets_matching(query: &str) -> Result<Vec<PathBuf>, PresetError> {
+    let presets_dir = presets_dir()?;
+    let mut files: Vec<PathBuf> = fs::read_dir(&presets_dir)
+        .map_err(|_| PresetError::ReadDir(presets_dir.clone()))?
         .filter_map(|res| {
             res.map_or(None, |file| {
                 if valid_extension(&file.path())
@@ -43,38 +203,331 @@ pub async fn handle_preset(
             })
         })
         .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Whether the file is named `query`, with or without its extension
+fn is_named(file: &Path, query: &str) -> bool {
+    file_name(file) == query
+        || file.file_stem().map(|stem| stem.to_string_lossy()) == Some(query.into())
+}
+
+/// The preset matching the query fuzzily
+///
+/// If several do, the one named exactly like the query, with or without its
+/// extension, is used.
+pub fn find_preset(query: &str) -> Result<PathBuf, PresetError> {
+    let files = presets_matching(query)?;
     if files.is_empty() {
-        exit!(1, "No matching presets found.")
+        return Err(PresetError::NoMatch);
     }
     if files.len() > 1 {
-        if let Some(strictly_filtered) = files.iter().find(|e| {
-            e.file_name()
-                .expect("all files have filenames")
-                .to_string_lossy()
-                == query
-        }) {
-            handle_file(client, strictly_filtered, noenv).await
-        } else {
-            exit!(
-                1,
-                "There where multiple files matching the query:\n{}",
-                files
-                    .iter()
-                    .map(|f| f
-                        .file_name()
-                        .expect("All files have filenames")
-                        .to_string_lossy()
-                        .to_string())
-                    .collect::<Vec<String>>()
-                    .join("\n")
-            )
+        files
+            .iter()
+            .find(|file| is_named(file, query))
+            .cloned()
+            .ok_or_else(|| {
+                PresetError::MultipleMatches(files.iter().map(|file| file_name(file)).collect())
+            })
+    } else {
+        Ok(files.into_iter().next().expect("files should not be empty"))
+    }
+}
+
+/// The file for a new preset called `name`, with `extension` unless the name has one
+///
+/// The name has to be a plain file name, not shadowed by a subcommand.
+fn new_preset_file(name: &str, extension: &str) -> Result<PathBuf, PresetError> {
+    let components: Vec<_> = Path::new(name).components().collect();
+    if name.contains(&['/', '\\'][..]) || !matches!(components[..], [Component::Normal(_)]) {
+        return Err(PresetError::InvalidName(name.to_string()));
+    }
+    let mut file = presets_dir()?.join(name);
+    if !valid_extension(&file) {
+        file = file.with_file_name(format!("{}.{}", name, extension));
+    }
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    if SUBCOMMANDS
+        .iter()
+        .any(|subcommand| matches_any!(stem, subcommand))
+    {
+        return Err(PresetError::SubcommandName(stem.to_string()));
+    }
+    if file.exists() {
+        return Err(PresetError::Exists(file_name(&file)));
+    }
+    Ok(file)
+}
+
+/// The other presets extending `file` by its name
+fn extended_by(file: &Path) -> Result<Vec<String>, PresetError> {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let name = file_name(file);
+    Ok(presets_matching("")?
+        .into_iter()
+        .filter(|preset| preset != file)
+        .filter(|preset| match read_config(preset) {
+            Ok(config) => config
+                .extends
+                .unwrap_or_default()
+                .iter()
+                .any(|extended| matches_any!(extended, &stem, &name)),
+            Err(_) => false,
+        })
+        .map(|preset| file_name(&preset))
+        .collect())
+}
+
+/// One line per preset matching `filter`, with its title and category
+///
+/// The values are the ones after merging in the presets it extends.
+pub fn summaries(filter: Option<&str>) -> Result<Vec<String>, PresetError> {
+    let presets = presets_matching(filter.unwrap_or_default())?;
+    let names: Vec<String> = presets.iter().map(|preset| file_name(preset)).collect();
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or_default();
+    Ok(presets
+        .iter()
+        .zip(&names)
+        .map(|(preset, name)| {
+            let summary = match resolve_config(preset) {
+                Ok(Config {
+                    title, category, ..
+                }) => {
+                    let title = title.unwrap_or_else(|| "-".to_string());
+                    match category {
+                        Some(category) => format!("{} [{}]", title, category),
+                        None => title,
+                    }
+                }
+                Err(e) => format!("invalid: {}", e),
+            };
+            format!("{:width$}  {}", name, summary, width = width)
+        })
+        .collect())
+}
+
+fn paint(style: Style, text: &str) -> String {
+    style.paint(text).to_string()
+}
+
+/// A value colored by its kind, strings green and numbers and booleans purple
+fn highlight_value(value: &str) -> String {
+    let trimmed = value.trim().trim_end_matches(',');
+    if trimmed.starts_with('"') || trimmed.starts_with('\'') {
+        paint(Green.normal(), value)
+    } else if trimmed.parse::<f64>().is_ok() || matches!(trimmed, "true" | "false" | "null") {
+        paint(Purple.normal(), value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// The config file in the format of the extension `format`, highlighted for a terminal
+///
+/// Comments are dimmed, sections yellow and keys blue.
+pub fn highlight(content: &str, format: &str) -> String {
+    let ini_like = matches_any!(format, "toml", "ini");
+    content
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+            if trimmed.starts_with('#') || (ini_like && trimmed.starts_with(';')) {
+                return paint(Style::new().dimmed(), line);
+            }
+            if ini_like && trimmed.starts_with('[') {
+                return paint(Yellow.bold(), line);
+            }
+            let key_end = if matches_any!(format, "json") {
+                trimmed
+                    .strip_prefix('"')
+                    .and_then(|rest| rest.find("\":"))
+                    .map(|end| indent + end + 3)
+            } else {
+                line.find(if ini_like { '=' } else { ':' })
+                    .map(|end| end + 1)
+            };
+            match key_end {
+                Some(end) if !line[..end - 1].trim().is_empty() => format!(
+                    "{}{}",
+                    paint(Blue.normal(), &line[..end]),
+                    highlight_value(&line[end..])
+                ),
+                _ => highlight_value(line),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Opens the preset in the editor until it is valid or the user gives up
+///
+/// A copy of the preset is edited, it is only replaced once the copy is valid.
+fn edit(file: &Path) -> Result<(), Box<dyn Error>> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let draft = env::temp_dir().join(format!(
+        "twitchctl-{}-{}",
+        std::process::id(),
+        file_name(file)
+    ));
+    fs::copy(file, &draft)?;
+    loop {
+        let mut words = editor.split_whitespace();
+        let status = Command::new(words.next().unwrap_or("vi"))
+            .args(words)
+            .arg(&draft)
+            .status()
+            .map_err(|e| PresetError::Editor(editor.clone(), e.to_string()))?;
+        if !status.success() {
+            return Err(PresetError::Editor(editor, status.to_string()).into());
         }
+
+        match resolve_draft(&draft, file) {
+            Ok(_) => {
+                fs::copy(&draft, file)?;
+                fs::remove_file(&draft)?;
+                println!("Saved `{}`.", file_name(file));
+                return Ok(());
+            }
+            Err(e) => {
+                // without a terminal to answer in, the draft is kept instead
+                if !atty::is(atty::Stream::Stdin) || !ask(&format!("{}\nEdit it again?", e), true)?
+                {
+                    return Err(PresetError::Invalid(e.to_string(), draft).into());
+                }
+            }
+        }
+    }
+}
+
+/// Asks a yes or no question, `default` unless answered with `y` or `n`
+fn ask(question: &str, default: bool) -> io::Result<bool> {
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(if default {
+        !answer.starts_with('n')
     } else {
-        handle_file(
-            client,
-            files.first().expect("files should not be empty"),
-            noenv,
-        )
-        .await
+        answer.starts_with('y')
+    })
+}
+
+pub async fn handle_preset(
+    client: ApiClient<'_>,
+    query: &str,
+    noenv: bool,
+    set: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    handle_file(client, &find_preset(query)?, noenv, set).await
+}
+
+/// Runs the preset subcommands, the client is only needed for `new --from-channel`
+pub async fn preset(
+    client: Option<ApiClient<'_>>,
+    subcommand: PresetSubcommand,
+) -> Result<(), Box<dyn Error>> {
+    match subcommand {
+        PresetSubcommand::Unexpected(args) => {
+            return Err(PresetError::Unexpected(args.join(" ")).into())
+        }
+        PresetSubcommand::List { filter } => {
+            for summary in summaries(filter.as_deref())? {
+                println!("{}", summary);
+            }
+        }
+        PresetSubcommand::Show { resolved, query } => {
+            let file = find_preset(&query)?;
+            let format = file.extension().unwrap_or_default().to_string_lossy();
+            let content = if resolved {
+                config_to_string(&resolve_config(&file)?, &format)?
+            } else {
+                fs::read_to_string(&file)?
+            };
+            if atty::is(atty::Stream::Stdout) {
+                println!("{}", highlight(&content, &format));
+            } else {
+                print!("{}", content);
+            }
+        }
+        PresetSubcommand::New {
+            name,
+            from_channel,
+            preset,
+        } => {
+            let file = new_preset_file(&name, "toml")?;
+            let mut config = match (from_channel, client) {
+                (true, Some(client)) => snapshot(&client, None).await?,
+                (true, None) => return Err(PresetError::NotConnected.into()),
+                (false, _) => Config::default(),
+            };
+            preset.apply_to(&mut config);
+            write_config(&config, &file)?;
+            println!("Created `{}`.", file_name(&file));
+        }
+        PresetSubcommand::Edit { query } => edit(&find_preset(&query)?)?,
+        PresetSubcommand::Rm { query } => {
+            let file = find_preset(&query)?;
+            if !is_named(&file, &query) {
+                // without a terminal to confirm in, only exact names are removed
+                if !atty::is(atty::Stream::Stdin) {
+                    return Err(PresetError::NotExact(query, file_name(&file)).into());
+                }
+                if !ask(&format!("Remove `{}`?", file_name(&file)), false)? {
+                    println!("Kept `{}`.", file_name(&file));
+                    return Ok(());
+                }
+            }
+            fs::remove_file(&file)?;
+            println!("Removed `{}`.", file_name(&file));
+            for preset in extended_by(&file)? {
+                warning!("`{}` extends the removed preset.", preset);
+            }
+        }
+        PresetSubcommand::Mv { query, name } => {
+            let file = find_preset(&query)?;
+            let extension = file.extension().unwrap_or_default().to_string_lossy();
+            let target = new_preset_file(&name, &extension)?;
+            let format = |file: &Path| {
+                let extension = file.extension().unwrap_or_default().to_string_lossy();
+                if matches_any!(extension, "yaml", "yml") {
+                    "yaml".to_string()
+                } else {
+                    extension.to_lowercase()
+                }
+            };
+            if format(&target) == format(&file) {
+                fs::rename(&file, &target)?;
+                println!(
+                    "Renamed `{}` to `{}`.",
+                    file_name(&file),
+                    file_name(&target)
+                );
+            } else {
+                write_config(&read_config(&file)?, &target)?;
+                fs::remove_file(&file)?;
+                println!(
+                    "Converted `{}` to `{}`, comments are not kept.",
+                    file_name(&file),
+                    file_name(&target)
+                );
+            }
+            for preset in extended_by(&file)? {
+                warning!(
+                    "`{}` extends the preset by its old name, change it to `{}`.",
+                    preset,
+                    target.file_stem().unwrap_or_default().to_string_lossy()
+                );
+            }
+        }
     }
+    Ok(())
 }
@@ -1,9 +1,14 @@
-use crate::api::{get_broadcaster_id_or_die, ApiClient};
-use crate::exit;
+use crate::api::{resolve_broadcaster_id, ApiClient};
 use fuzzy_filter::FuzzyFilter;
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{convert::TryFrom, error::Error, fmt};
 use structopt::StructOpt;
 use twitch_api2::helix::points::{CreateCustomRewardBody, CustomReward, UpdateCustomRewardBody};
-use twitch_api2::types::{UserId, UserName};
+use twitch_api2::twitch_oauth2::Scope;
+use twitch_api2::types::{Max, UserId, UserName};
 
 #[derive(Debug, StructOpt)]
 /// manipulate a streams tags
@@ -44,6 +49,18 @@ pub enum RewardsSubcommand {
     },
 }
 
+impl RewardsSubcommand {
+    /// The scopes needed to run this command
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            RewardsSubcommand::List { .. } => vec![Scope::ChannelReadRedemptions],
+            RewardsSubcommand::Add { .. } | RewardsSubcommand::Update { .. } => {
+                vec![Scope::ChannelManageRedemptions]
+            }
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 pub struct RewardOption {
     /// the title of the reward
@@ -178,8 +195,20 @@ impl From<RewardOption> for UpdateCustomRewardBody {
             .build()
     }
 }
-impl From<RewardOption> for CreateCustomRewardBody {
-    fn from(r: RewardOption) -> Self {
+#[derive(thiserror::Error, Debug)]
+pub enum RewardsError {
+    #[error("Title and cost are required to create a new reward.")]
+    MissingTitleOrCost,
+    #[error("Did not find a unique reward matching `{0}`")]
+    NoUniqueReward(String),
+    #[error("The reward `{0}` does not exist yet and needs a cost to be created.")]
+    MissingCost(String),
+}
+
+impl TryFrom<RewardOption> for CreateCustomRewardBody {
+    type Error = RewardsError;
+
+    fn try_from(r: RewardOption) -> Result<Self, Self::Error> {
         match r {
             RewardOption {
                 title: Some(title),
@@ -193,7 +222,7 @@ impl From<RewardOption> for CreateCustomRewardBody {
                 cooldown,
                 auto_fulfill,
                 ..
-            } => CreateCustomRewardBody::builder()
+            } => Ok(CreateCustomRewardBody::builder()
                 .title(title)
                 .cost(cost)
                 .prompt(prompt)
@@ -219,8 +248,248 @@ impl From<RewardOption> for CreateCustomRewardBody {
                     None
                 })
                 .should_redemptions_skip_request_queue(auto_fulfill)
-                .build(),
-            _ => exit!(1, "Title and cost are required to create a new reward."),
+                .build()),
+            _ => Err(RewardsError::MissingTitleOrCost),
+        }
+    }
+}
+
+/// A reward in a config file, only its title or its whole definition
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RewardConfig {
+    Title(String),
+    Definition(RewardDefinition),
+}
+
+impl RewardConfig {
+    pub fn title(&self) -> &str {
+        match self {
+            RewardConfig::Title(title) => title,
+            RewardConfig::Definition(definition) => &definition.title,
+        }
+    }
+}
+
+// by hand instead of untagged, so the definition is deserialized from the
+// format itself and INI values keep being typed by their field
+impl<'de> Deserialize<'de> for RewardConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RewardVisitor;
+
+        impl<'de> Visitor<'de> for RewardVisitor {
+            type Value = RewardConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("the title or the definition of a reward")
+            }
+
+            fn visit_str<E: de::Error>(self, title: &str) -> Result<RewardConfig, E> {
+                Ok(RewardConfig::Title(title.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<RewardConfig, A::Error> {
+                RewardDefinition::deserialize(MapAccessDeserializer::new(map))
+                    .map(RewardConfig::Definition)
+            }
+        }
+
+        deserializer.deserialize_any(RewardVisitor)
+    }
+}
+
+/// A reward defined in a config file
+///
+/// It is created when missing and updated when it differs from the definition,
+/// a limit or cooldown of 0 disables it.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct RewardDefinition {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// hex with # prefix, like #00E5CB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_stream: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_user: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_input: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_fulfill: Option<bool>,
+}
+
+impl From<&RewardDefinition> for RewardOption {
+    fn from(definition: &RewardDefinition) -> Self {
+        RewardOption {
+            title: Some(definition.title.clone()),
+            cost: definition.cost,
+            prompt: definition.prompt.clone(),
+            enabled: true,
+            disabled: false,
+            color: definition.color.clone(),
+            user_input: definition.user_input == Some(true),
+            no_user_input: definition.user_input == Some(false),
+            max_per_stream: definition.max_per_stream,
+            max_per_user: definition.max_per_user,
+            cooldown: definition.cooldown,
+            auto_fulfill: definition.auto_fulfill == Some(true),
+            no_auto_fulfill: definition.auto_fulfill == Some(false),
+            paused: false,
+            not_paused: false,
+        }
+    }
+}
+
+/// The limit of the setting, 0 if it is disabled
+fn limit(max: &Max) -> usize {
+    match *max {
+        Max::MaxPerStream {
+            is_enabled: true,
+            max_per_stream,
+        } => max_per_stream as usize,
+        Max::MaxPerUserPerStream {
+            is_enabled: true,
+            max_per_user_per_stream,
+        } => max_per_user_per_stream as usize,
+        _ => 0,
+    }
+}
+
+/// The cooldown of the reward in seconds, 0 if it is disabled
+fn cooldown(reward: &CustomReward) -> usize {
+    let cooldown = &reward.global_cooldown_setting;
+    if cooldown.is_enabled {
+        cooldown.global_cooldown_seconds as usize
+    } else {
+        0
+    }
+}
+
+/// Everything of the reward, to set it back to how it is
+impl From<&CustomReward> for RewardOption {
+    fn from(reward: &CustomReward) -> Self {
+        RewardOption {
+            title: Some(reward.title.clone()),
+            cost: Some(reward.cost),
+            prompt: Some(reward.prompt.clone()),
+            enabled: reward.is_enabled,
+            disabled: !reward.is_enabled,
+            color: Some(reward.background_color.clone()),
+            user_input: reward.is_user_input_required,
+            no_user_input: !reward.is_user_input_required,
+            max_per_stream: Some(limit(&reward.max_per_stream_setting)),
+            max_per_user: Some(limit(&reward.max_per_user_per_stream_setting)),
+            cooldown: Some(cooldown(reward)),
+            auto_fulfill: reward.should_redemptions_skip_request_queue,
+            no_auto_fulfill: !reward.should_redemptions_skip_request_queue,
+            paused: reward.is_paused,
+            not_paused: !reward.is_paused,
+        }
+    }
+}
+
+impl RewardDefinition {
+    /// The body creating the reward, enabled
+    pub fn create_body(&self) -> Result<CreateCustomRewardBody, RewardsError> {
+        if self.cost.is_none() {
+            return Err(RewardsError::MissingCost(self.title.clone()));
+        }
+        CreateCustomRewardBody::try_from(RewardOption::from(self))
+    }
+
+    /// The body updating what `reward` differs in from the definition, `None` if nothing does
+    ///
+    /// Disabled rewards are enabled as well. The differences are returned as
+    /// `field current -> defined`.
+    pub fn update_body(
+        &self,
+        reward: &CustomReward,
+    ) -> Option<(UpdateCustomRewardBody, Vec<String>)> {
+        let mut option = RewardOption::from(self);
+        let mut changes = vec![];
+        if reward.title == self.title {
+            option.title = None;
+        } else {
+            changes.push(format!("title {:?} -> {:?}", reward.title, self.title));
+        }
+        match self.cost {
+            Some(cost) if cost != reward.cost => {
+                changes.push(format!("cost {} -> {}", reward.cost, cost))
+            }
+            _ => option.cost = None,
+        }
+        match &self.prompt {
+            Some(prompt) if *prompt != reward.prompt => {
+                changes.push(format!("prompt {:?} -> {:?}", reward.prompt, prompt))
+            }
+            _ => option.prompt = None,
+        }
+        match &self.color {
+            Some(color) if !color.eq_ignore_ascii_case(&reward.background_color) => {
+                changes.push(format!("color {} -> {}", reward.background_color, color))
+            }
+            _ => option.color = None,
+        }
+        let cooldown = cooldown(reward);
+        match self.cooldown {
+            Some(seconds) if seconds != cooldown => {
+                changes.push(format!("cooldown {} -> {}", cooldown, seconds))
+            }
+            _ => option.cooldown = None,
+        }
+        let max_per_stream = limit(&reward.max_per_stream_setting);
+        match self.max_per_stream {
+            Some(max) if max != max_per_stream => {
+                changes.push(format!("max per stream {} -> {}", max_per_stream, max))
+            }
+            _ => option.max_per_stream = None,
+        }
+        let max_per_user = limit(&reward.max_per_user_per_stream_setting);
+        match self.max_per_user {
+            Some(max) if max != max_per_user => {
+                changes.push(format!("max per user {} -> {}", max_per_user, max))
+            }
+            _ => option.max_per_user = None,
+        }
+        match self.user_input {
+            Some(user_input) if user_input != reward.is_user_input_required => {
+                changes.push(format!(
+                    "user input {} -> {}",
+                    reward.is_user_input_required, user_input
+                ))
+            }
+            _ => {
+                option.user_input = false;
+                option.no_user_input = false;
+            }
+        }
+        let skip_queue = reward.should_redemptions_skip_request_queue;
+        match self.auto_fulfill {
+            Some(auto_fulfill) if auto_fulfill != skip_queue => {
+                changes.push(format!("auto fulfill {} -> {}", skip_queue, auto_fulfill))
+            }
+            _ => {
+                option.auto_fulfill = false;
+                option.no_auto_fulfill = false;
+            }
+        }
+        if reward.is_enabled {
+            option.enabled = false;
+        } else {
+            changes.push("enabled".to_string());
+        }
+
+        if changes.is_empty() {
+            None
+        } else {
+            Some((option.into(), changes))
         }
     }
 }
@@ -236,7 +505,10 @@ pub struct BroadcasterOption {
     broadcaster_id: Option<UserId>,
 }
 
-pub async fn rewards(client: ApiClient<'_>, command: RewardsSubcommand) {
+pub async fn rewards(
+    client: ApiClient<'_>,
+    command: RewardsSubcommand,
+) -> Result<(), Box<dyn Error>> {
     match command {
         RewardsSubcommand::List {
             long,
@@ -247,12 +519,8 @@ pub async fn rewards(client: ApiClient<'_>, command: RewardsSubcommand) {
                     broadcaster_id,
                 },
         } => {
-            let id = get_broadcaster_id_or_die(&client, broadcaster, broadcaster_id).await;
-            let rewards = client.get_rewards(&id);
-            match rewards.await {
-                Ok(rewards) => list(&rewards, filter, long),
-                Err(e) => exit!(1, "An error occurred while fetching the rewards: {}", e),
-            }
+            let id = resolve_broadcaster_id(&client, broadcaster, broadcaster_id).await?;
+            list(&client.get_rewards(&id).await?, filter, long);
         }
         RewardsSubcommand::Add {
             broadcaster:
@@ -263,16 +531,12 @@ pub async fn rewards(client: ApiClient<'_>, command: RewardsSubcommand) {
             reward,
         } => {
             let broadcaster_id =
-                get_broadcaster_id_or_die(&client, broadcaster, broadcaster_id).await;
+                resolve_broadcaster_id(&client, broadcaster, broadcaster_id).await?;
 
             // TODO Update Paused Status
-            match client
-                .create_custom_reward(&broadcaster_id, reward.into())
-                .await
-            {
-                Ok(_) => {}
-                Err(e) => exit!(1, "{}", e),
-            }
+            client
+                .create_custom_reward(&broadcaster_id, CreateCustomRewardBody::try_from(reward)?)
+                .await?;
         }
         RewardsSubcommand::Update {
             broadcaster:
@@ -284,29 +548,20 @@ pub async fn rewards(client: ApiClient<'_>, command: RewardsSubcommand) {
             current_title,
         } => {
             let broadcaster_id =
-                get_broadcaster_id_or_die(&client, broadcaster, broadcaster_id).await;
+                resolve_broadcaster_id(&client, broadcaster, broadcaster_id).await?;
 
-            if let Some(CustomReward { id, title, .. }) = client
-                .find_reward(&broadcaster_id, &current_title)
-                .await
-                .unwrap_or_else(|e| exit!(1, "{}", e))
-            {
-                match client
-                    .update_custom_reward(&broadcaster_id, &id, reward.into())
-                    .await
-                {
-                    Ok(_) => println!("Updated: `{}`", title),
-                    Err(e) => exit!(1, "{}", e),
+            match client.find_reward(&broadcaster_id, &current_title).await? {
+                Some(CustomReward { id, title, .. }) => {
+                    client
+                        .update_custom_reward(&broadcaster_id, &id, reward.into())
+                        .await?;
+                    println!("Updated: `{}`", title);
                 }
-            } else {
-                exit!(
-                    1,
-                    "Did not find a unique reward matching `{}`",
-                    current_title
-                )
+                None => return Err(RewardsError::NoUniqueReward(current_title).into()),
             }
         }
     }
+    Ok(())
 }
 
 fn list(rewards: &[CustomReward], filter: Option<String>, long: bool) {
@@ -0,0 +1,77 @@
+use std::{error::Error, path::Path};
+use twitch_api2::helix::tags::AutoGenerated;
+
+use crate::{
+    api::{ApiClient, ApiError},
+    file::{config_to_string, write_config, Config},
+    plan::tag_name,
+    rewards::RewardConfig,
+    warning,
+};
+
+/// Reads the channel of the client's user as a config
+///
+/// The config has the title, category, language, manually set tags and
+/// enabled rewards of the channel. The tags are named in `locale`, or in
+/// English if they have no name in it. Tags without either name are left
+/// out with a warning, as their id would not be found by name again.
+pub async fn snapshot(client: &ApiClient<'_>, locale: Option<String>) -> Result<Config, ApiError> {
+    let broadcaster = client.get_user_id()?;
+    let locale = locale.unwrap_or_else(|| client.locale().to_string());
+
+    let channel = client
+        .get_channel_information(broadcaster)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("channel {}", broadcaster)))?;
+    let tags = client
+        .get_stream_tags(broadcaster)
+        .await?
+        .iter()
+        .filter(|tag| tag.is_auto == AutoGenerated::False)
+        .filter_map(|tag| {
+            let name = tag_name(tag, &locale).cloned();
+            if name.is_none() {
+                warning!(
+                    "The tag `{}` has no name in {} or English and is left out.",
+                    tag.id,
+                    locale
+                );
+            }
+            name
+        })
+        .collect();
+    let rewards = client
+        .get_rewards(broadcaster)
+        .await?
+        .into_iter()
+        .filter(|reward| reward.is_enabled)
+        .map(|reward| RewardConfig::Title(reward.title))
+        .collect();
+
+    Ok(Config {
+        extends: None,
+        replace: None,
+        title: Some(channel.title),
+        // channels without a category have an empty one
+        category: Some(channel.game_name).filter(|name| !name.is_empty()),
+        language: Some(channel.broadcaster_language),
+        config_locale: Some(locale),
+        tags: Some(tags),
+        notification: None,
+        rewards: Some(rewards),
+    })
+}
+
+/// Writes the channel as config to `output`, or prints it as TOML without one
+pub async fn handle_snapshot(
+    client: ApiClient<'_>,
+    output: Option<&Path>,
+    locale: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let config = snapshot(&client, locale).await?;
+    match output {
+        Some(file) => write_config(&config, file)?,
+        None => print!("{}", config_to_string(&config, "toml")?),
+    }
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use crate::api::{ApiClient, get_broadcaster_id_or_die};
-use crate::{exit, warning};
+use crate::api::{resolve_broadcaster_id, ApiClient};
+use crate::cache::TagCache;
+use crate::warning;
 use fuzzy_filter::FuzzyFilter;
+use std::error::Error;
 use structopt::StructOpt;
 use twitch_api2::{
     helix::tags::{AutoGenerated, TwitchTag},
+    twitch_oauth2::Scope,
     types::{UserId, UserName},
 };
 
@@ -11,8 +14,10 @@ use twitch_api2::{
 /// manipulate a streams tags
 pub struct TagsOptions {
     /// the locale to use for the tag names
-    #[structopt(short, long, default_value = "en-us")]
-    pub locale: String,
+    ///
+    /// Defaults to the locale of the profile or `en-us`.
+    #[structopt(short, long)]
+    pub locale: Option<String>,
 
     #[structopt(subcommand)]
     pub subcommand: TagsSubcommand,
@@ -47,6 +52,16 @@ pub enum TagsSubcommand {
     },
 }
 
+impl TagsSubcommand {
+    /// The scopes needed to run this command
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            TagsSubcommand::ListAll { .. } | TagsSubcommand::List { .. } => vec![],
+            TagsSubcommand::Set { .. } => vec![Scope::ChannelManageBroadcast],
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 pub struct BroadcasterOption {
     /// the name of the broadcaster
@@ -67,14 +82,21 @@ pub struct SharedTagsOptions {
     filter: Option<String>,
 }
 
-pub async fn tags(client: ApiClient<'_>, locale: &str, command: TagsSubcommand) {
+#[derive(thiserror::Error, Debug)]
+pub enum TagsError {
+    #[error("The locale '{0}' was not found in the tags returned by Twitch")]
+    UnknownLocale(String),
+}
+
+pub async fn tags(
+    client: ApiClient<'_>,
+    locale: &str,
+    command: TagsSubcommand,
+) -> Result<(), Box<dyn Error>> {
     match command {
         TagsSubcommand::ListAll {
             shared: SharedTagsOptions { long, filter },
-        } => match client.get_all_tags().await {
-            Ok(tags) => list(&tags, locale, filter, long),
-            Err(e) => exit!(1, "An error occurred while fetching the tags: {}", e),
-        },
+        } => list(&client.get_all_tags().await?, locale, filter, long)?,
 
         TagsSubcommand::List {
             shared: SharedTagsOptions { long, filter },
@@ -84,12 +106,8 @@ pub async fn tags(client: ApiClient<'_>, locale: &str, command: TagsSubcommand)
                     broadcaster_id,
                 },
         } => {
-            let id = get_broadcaster_id_or_die(&client, broadcaster, broadcaster_id).await;
-            let tags = client.get_stream_tags(&id);
-            match tags.await {
-                Ok(tags) => list(&tags, locale, filter, long),
-                Err(e) => exit!(1, "An error occurred while fetching the tags: {}", e),
-            }
+            let id = resolve_broadcaster_id(&client, broadcaster, broadcaster_id).await?;
+            list(&client.get_stream_tags(&id).await?, locale, filter, long)?;
         }
 
         TagsSubcommand::Set {
@@ -102,16 +120,17 @@ pub async fn tags(client: ApiClient<'_>, locale: &str, command: TagsSubcommand)
             add,
         } => {
             let broadcaster_id =
-                get_broadcaster_id_or_die(&client, broadcaster, broadcaster_id).await;
+                resolve_broadcaster_id(&client, broadcaster, broadcaster_id).await?;
             let current_tags = async {
                 if add {
                     client
                         .get_stream_tags(&broadcaster_id)
                         .await
-                        .unwrap_or(vec![])
+                        .unwrap_or_default()
                         .into_iter()
+                        // automatic tags can not be set and are kept by twitch anyway
                         .filter_map(|v| {
-                            if v.is_auto == AutoGenerated::True {
+                            if v.is_auto == AutoGenerated::False {
                                 Some(v.id)
                             } else {
                                 None
@@ -123,14 +142,7 @@ pub async fn tags(client: ApiClient<'_>, locale: &str, command: TagsSubcommand)
                 }
             };
 
-            let mut new_tags = match client.get_tag_ids_matching(tags.as_slice(), locale).await {
-                Ok(tags) => tags,
-                Err(_) => exit!(
-                    1,
-                    "Did not receive any localized data for locale {}",
-                    locale
-                ),
-            };
+            let mut new_tags = client.get_tag_ids_matching(tags.as_slice(), locale).await?;
 
             if new_tags.len() != tags.len() {
                 warning!(
@@ -141,31 +153,55 @@ pub async fn tags(client: ApiClient<'_>, locale: &str, command: TagsSubcommand)
 
             let mut current_tags = current_tags.await;
             current_tags.append(&mut new_tags);
-            match client
+            client
                 .replace_stream_tags(&broadcaster_id, current_tags)
-                .await
-            {
-                Ok(_) => {}
-                Err(e) => exit!(1, "{}", e),
-            }
+                .await?;
         }
     }
+    Ok(())
 }
 
-fn list(tags: &[TwitchTag], locale: &str, filter: Option<String>, long: bool) {
+/// Runs `list-all` from the tag cache, for when Twitch can't be reached
+///
+/// Returns `false` for other commands or if no tags are cached.
+pub fn list_cached(
+    cache: &TagCache,
+    helix_url: &str,
+    locale: &str,
+    command: TagsSubcommand,
+) -> Result<bool, TagsError> {
+    match (command, cache.load(helix_url)) {
+        (
+            TagsSubcommand::ListAll {
+                shared: SharedTagsOptions { long, filter },
+            },
+            Some((_, tags)),
+        ) => {
+            warning!("Could not reach Twitch, listing the cached tags.");
+            list(&tags, locale, filter, long)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn list(
+    tags: &[TwitchTag],
+    locale: &str,
+    filter: Option<String>,
+    long: bool,
+) -> Result<(), TagsError> {
     let filter = filter.as_ref().map(|f| f.to_lowercase());
     let filter = filter.as_ref().map(|f| FuzzyFilter::new(f));
 
     let max_len = tags
         .iter()
         .map(|tag| match tag.localization_names.get(locale) {
-            Some(localized_name) => localized_name.len(),
-            _ => exit!(
-                1,
-                "The locale '{}' was not found in the tags returned by Twitch",
-                locale
-            ),
+            Some(localized_name) => Ok(localized_name.len()),
+            _ => Err(TagsError::UnknownLocale(locale.to_string())),
         })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
         .max()
         .unwrap_or(0);
 
@@ -194,4 +230,5 @@ fn list(tags: &[TwitchTag], locale: &str, filter: Option<String>, long: bool) {
     if !long {
         println!()
     }
+    Ok(())
 }
@@ -0,0 +1,200 @@
+//! Titles with values filled in when applying them
+//!
+//! A title like `Building twitchctl – day {{counter.devlog}} ({{date:%Y-%m-%d}})`
+//! is rendered with
+//!
+//! - `{{counter.name}}`: a counter stored in the config folder, one higher
+//!   every time a title using it is applied
+//! - `{{date}}` or `{{date:format}}`: today's date, in a `strftime` format
+//! - `{{env.NAME}}`: the environment variable `NAME`
+//! - `{{name}}`: the value given with `--set name=value`
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+use crate::config::config_dir;
+
+#[derive(thiserror::Error, Debug)]
+pub enum TemplateError {
+    #[error("Expected `key=value`, got `{0}`.")]
+    InvalidSet(String),
+    #[error("The `{{{{` at character {1} of `{0}` is not closed.")]
+    Unclosed(String, usize),
+    #[error("Unknown template value `{0}`, give it with `--set {0}=...`.")]
+    Unknown(String),
+    #[error("The environment variable `{0}` used in the title is not set.")]
+    Env(String),
+    #[error("Invalid date format `{0}`.")]
+    Date(String),
+    #[error("Unable to find a config folder to store counters in.")]
+    NoCounters,
+    #[error("Unable to read the counters in `{}`: {1}", .0.display())]
+    ReadCounters(PathBuf, String),
+    #[error("Unable to store the counters in `{}`: {1}", .0.display())]
+    WriteCounters(PathBuf, String),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TemplateOptions {
+    /// a value for the title, `--set game=Celeste` fills in `{{game}}`
+    #[structopt(
+        long = "set",
+        value_name = "key=value",
+        number_of_values = 1,
+        parse(try_from_str = parse_set)
+    )]
+    pub set: Vec<(String, String)>,
+}
+
+fn parse_set(set: &str) -> Result<(String, String), TemplateError> {
+    match set.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(TemplateError::InvalidSet(set.to_string())),
+    }
+}
+
+/// The named counters, stored in `counters.toml`
+#[derive(Clone, Debug)]
+pub struct Counters {
+    file: PathBuf,
+}
+
+impl Counters {
+    /// The counters in the platform specific config folder, `None` if there is none
+    pub fn new() -> Option<Counters> {
+        config_dir().map(Counters::in_dir)
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Counters {
+        Counters {
+            file: dir.into().join("counters.toml"),
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The stored counts, counters that were never applied are missing
+    pub fn load(&self) -> Result<BTreeMap<String, u64>, TemplateError> {
+        let read_error = |e: &dyn std::error::Error| {
+            TemplateError::ReadCounters(self.file.clone(), e.to_string())
+        };
+        match fs::read_to_string(&self.file) {
+            Ok(content) => toml::from_str(&content).map_err(|e| read_error(&e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(read_error(&e)),
+        }
+    }
+
+    /// Counts the counters up by one
+    pub fn increment(&self, names: &[String]) -> Result<(), TemplateError> {
+        let mut counts = self.load()?;
+        for name in names {
+            *counts.entry(name.clone()).or_default() += 1;
+        }
+        let write_error = |e: &dyn std::error::Error| {
+            TemplateError::WriteCounters(self.file.clone(), e.to_string())
+        };
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir).map_err(|e| write_error(&e))?;
+        }
+        let content = toml::to_string(&counts).map_err(|e| write_error(&e))?;
+        fs::write(&self.file, content).map_err(|e| write_error(&e))
+    }
+}
+
+/// The values templates are rendered with
+#[derive(Clone, Debug)]
+pub struct Values {
+    set: Vec<(String, String)>,
+    counters: Option<Counters>,
+}
+
+impl Values {
+    /// The values given with `--set` and the counters in the config folder
+    pub fn new(set: Vec<(String, String)>) -> Values {
+        Values {
+            set,
+            counters: Counters::new(),
+        }
+    }
+
+    pub fn with_counters(mut self, counters: Counters) -> Values {
+        self.counters = Some(counters);
+        self
+    }
+
+    pub fn counters(&self) -> Option<&Counters> {
+        self.counters.as_ref()
+    }
+}
+
+/// A rendered template
+#[derive(Debug, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    /// the counters used, to be incremented once the text is applied
+    pub counters: Vec<String>,
+}
+
+/// Fills the values into `template`
+///
+/// Counters are rendered one higher than stored, as they are once the text
+/// is applied.
+pub fn render(template: &str, values: &Values) -> Result<Rendered, TemplateError> {
+    let mut text = String::new();
+    let mut counters = vec![];
+    let mut counts = None;
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        text.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").ok_or_else(|| {
+            let position = template[..template.len() - rest.len() + start]
+                .chars()
+                .count();
+            TemplateError::Unclosed(template.to_string(), position + 1)
+        })?;
+        let key = rest[start + 2..start + end].trim();
+        rest = &rest[start + end + 2..];
+
+        if let Some(name) = key.strip_prefix("counter.") {
+            if counts.is_none() {
+                counts = Some(values.counters().ok_or(TemplateError::NoCounters)?.load()?);
+            }
+            let count = counts.as_ref().and_then(|c| c.get(name)).copied();
+            text.push_str(&(count.unwrap_or_default() + 1).to_string());
+            if !counters.iter().any(|counter| counter == name) {
+                counters.push(name.to_string());
+            }
+        } else if key == "date" || key.starts_with("date:") {
+            let format = key.strip_prefix("date:").unwrap_or("%Y-%m-%d");
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(TemplateError::Date(format.to_string()));
+            }
+            text.push_str(&Local::now().format(format).to_string());
+        } else if let Some(name) = key.strip_prefix("env.") {
+            let value = env::var(name).map_err(|_| TemplateError::Env(name.to_string()))?;
+            text.push_str(&value);
+        } else {
+            let (_, value) = values
+                .set
+                .iter()
+                .find(|(set, _)| set == key)
+                .ok_or_else(|| TemplateError::Unknown(key.to_string()))?;
+            text.push_str(value);
+        }
+    }
+    text.push_str(rest);
+    Ok(Rendered { text, counters })
+}
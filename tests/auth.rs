@@ -0,0 +1,444 @@
+//! Logs in against the fake id.twitch.tv, refreshes expired tokens and stores them in the profiles
+
+mod common;
+
+use common::{
+    FakeTwitch, Oauth, State, CLIENT_ID, CLIENT_SECRET, LOGIN, REFRESH_TOKEN, TOKEN, USER_ID,
+};
+use std::{env, fs, sync::Once, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{oneshot, Mutex, MutexGuard},
+    time::{self, Instant},
+};
+use twitch_api2::twitch_oauth2::oauth2::url::Url;
+use twitchctl::{
+    auth::{login, revoke, status, Browser},
+    config::{load_profile, save_token, DotEnv, TokenSource},
+    ApiError,
+};
+
+/// Points the config folder at a fresh directory
+///
+/// The tests of this file share it, so they are run one after another.
+async fn config_home() -> MutexGuard<'static, ()> {
+    static CONFIG_HOME: Mutex<()> = Mutex::const_new(());
+    static INIT: Once = Once::new();
+    let lock = CONFIG_HOME.lock().await;
+    INIT.call_once(|| {
+        let dir = env::temp_dir().join(format!("twitchctl-{}-auth", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        env::set_var("XDG_CONFIG_HOME", &dir);
+    });
+    lock
+}
+
+fn twitch(oauth: Oauth) -> FakeTwitch {
+    FakeTwitch::new(State {
+        oauth,
+        ..State::seeded()
+    })
+}
+
+#[tokio::test]
+async fn device_login_polls_until_authorized() {
+    let _home = config_home().await;
+    time::pause();
+    let twitch = twitch(Oauth {
+        pending: vec!["authorization_pending", "slow_down"],
+        ..Oauth::default()
+    });
+    let start = Instant::now();
+
+    login(&twitch.http(), "device", common::CLIENT_ID, None, false)
+        .await
+        .unwrap();
+
+    // polled every 5 seconds, and every 10 after being asked to slow down
+    assert_eq!(start.elapsed().as_secs(), 5 + 5 + 10);
+    assert_eq!(
+        twitch.state().requests,
+        [
+            "POST /oauth2/device",
+            "POST /oauth2/token",
+            "POST /oauth2/token",
+            "POST /oauth2/token",
+            "GET /oauth2/validate"
+        ]
+    );
+    let profile = load_profile(Some("device")).unwrap();
+    assert_eq!(profile.access_token.as_deref(), Some("fake-token-1"));
+    assert_eq!(
+        profile.refresh_token.as_deref(),
+        Some("fake-refresh-token-1")
+    );
+    assert_eq!(profile.client_id.as_deref(), Some(common::CLIENT_ID));
+}
+
+#[tokio::test]
+async fn denied_or_expired_device_logins_store_nothing() {
+    let _home = config_home().await;
+    time::pause();
+    for (answer, message) in &[
+        ("access_denied", "The login was denied."),
+        (
+            "expired_token",
+            "The device code expired before the login was authorized.",
+        ),
+    ] {
+        let twitch = twitch(Oauth {
+            pending: vec!["authorization_pending", answer],
+            ..Oauth::default()
+        });
+
+        let error = login(&twitch.http(), "denied", common::CLIENT_ID, None, false)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), *message);
+        assert_eq!(load_profile(Some("denied")).unwrap().access_token, None);
+    }
+}
+
+/// The tokens of `profile` in `profiles.toml`, with a refresh token
+fn stored_env(profile: &str) -> DotEnv {
+    DotEnv {
+        refresh_token: Some(REFRESH_TOKEN.to_string()),
+        client_id: Some(CLIENT_ID.to_string()),
+        profile: profile.to_string(),
+        source: TokenSource::Profile,
+        ..common::env()
+    }
+}
+
+#[tokio::test]
+async fn expired_tokens_are_refreshed_once_and_stored() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+    let client = twitch.client_for(&stored_env("refresh")).await.unwrap();
+    let user = client.get_user_id().unwrap().clone();
+    twitch.state().oauth.expired = true;
+
+    client.get_stream_tags(&user).await.unwrap();
+    client.get_stream_tags(&user).await.unwrap();
+
+    assert_eq!(
+        twitch.state().requests[1..],
+        [
+            "GET /helix/streams/tags",
+            "POST /oauth2/token",
+            "GET /oauth2/validate",
+            "GET /helix/streams/tags",
+            "GET /helix/streams/tags"
+        ]
+    );
+    let profile = load_profile(Some("refresh")).unwrap();
+    assert_eq!(profile.access_token.as_deref(), Some("fake-token-1"));
+    assert_eq!(
+        profile.refresh_token.as_deref(),
+        Some("fake-refresh-token-1")
+    );
+}
+
+#[tokio::test]
+async fn tokens_rejected_after_refreshing_are_not_refreshed_again() {
+    let _home = config_home().await;
+    let twitch = FakeTwitch::new(State {
+        failures: vec![("GET /helix/streams/tags", 401); 2],
+        ..State::seeded()
+    });
+    let client = twitch.client_for(&stored_env("rejected")).await.unwrap();
+    let user = client.get_user_id().unwrap().clone();
+
+    let error = client.get_stream_tags(&user).await.unwrap_err();
+
+    assert!(matches!(error, ApiError::Unauthorized(_)));
+    let state = twitch.state();
+    let count = |request: &str| state.requests.iter().filter(|r| *r == request).count();
+    assert_eq!(count("POST /oauth2/token"), 1);
+    assert_eq!(count("GET /helix/streams/tags"), 2);
+}
+
+#[tokio::test]
+async fn tokens_from_the_env_are_refreshed_but_not_stored() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+    let env = DotEnv {
+        source: TokenSource::Env,
+        ..stored_env("env")
+    };
+    let client = twitch.client_for(&env).await.unwrap();
+    let user = client.get_user_id().unwrap().clone();
+    twitch.state().oauth.expired = true;
+
+    client.get_stream_tags(&user).await.unwrap();
+
+    assert_eq!(twitch.state().oauth.issued, 1);
+    assert_eq!(load_profile(Some("env")).unwrap().access_token, None);
+}
+
+/// A browser authorizing twitchctl at the fake and following the redirect to the login
+///
+/// The redirect carries `state` instead of the one of the login, if given. The
+/// status line of the page shown after the redirect is sent to `page`.
+fn browser(
+    twitch: &FakeTwitch,
+    state: Option<&'static str>,
+    page: oneshot::Sender<String>,
+) -> Box<dyn FnOnce(&Url)> {
+    let http = twitch.http();
+    Box::new(move |url| {
+        let request = http::Request::get(url.as_str()).body(vec![]).unwrap();
+        tokio::spawn(async move {
+            let response = http.send(request).await.unwrap();
+            let location = response.headers()["Location"].to_str().unwrap();
+            let mut redirect = Url::parse(location).unwrap();
+            if let Some(state) = state {
+                let query: Vec<(String, String)> = redirect
+                    .query_pairs()
+                    .into_owned()
+                    .map(|(k, v)| {
+                        if k == "state" {
+                            (k, state.to_string())
+                        } else {
+                            (k, v)
+                        }
+                    })
+                    .collect();
+                redirect.query_pairs_mut().clear().extend_pairs(query);
+            }
+
+            let address = format!(
+                "{}:{}",
+                redirect.host_str().unwrap(),
+                redirect.port().unwrap()
+            );
+            let mut stream = TcpStream::connect(&address).await.unwrap();
+            let request = format!(
+                "GET /?{} HTTP/1.1\r\nHost: {}\r\n\r\n",
+                redirect.query().unwrap(),
+                address
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            let _ = page.send(response.lines().next().unwrap_or_default().to_string());
+        });
+    })
+}
+
+/// Logs in as `profile` in the browser, giving up after a few seconds instead of waiting for the redirect
+async fn browser_login<'a>(
+    twitch: &'a FakeTwitch,
+    profile: &'a str,
+    store_secret: bool,
+    state: Option<&'static str>,
+) -> (Result<(), String>, String) {
+    let (page, shown) = oneshot::channel();
+    let browser = Browser {
+        port: 0,
+        client_secret: CLIENT_SECRET,
+        store_secret,
+        open: browser(twitch, state, page),
+    };
+    let http = twitch.http();
+    let login = login(&http, profile, CLIENT_ID, Some(browser), false);
+    let result = time::timeout(Duration::from_secs(10), login)
+        .await
+        .expect("the browser followed the redirect");
+    (result.map_err(|e| e.to_string()), shown.await.unwrap())
+}
+
+#[tokio::test]
+async fn browser_login_exchanges_the_code_of_the_redirect() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+
+    let (result, page) = browser_login(&twitch, "browser", true, None).await;
+
+    result.unwrap();
+    assert_eq!(page, "HTTP/1.1 200 OK");
+    let state = twitch.state();
+    assert_eq!(
+        state.requests,
+        [
+            "GET /oauth2/authorize",
+            "POST /oauth2/token",
+            "GET /oauth2/validate"
+        ]
+    );
+    assert!(state
+        .oauth
+        .redirect_uri
+        .as_ref()
+        .unwrap()
+        .starts_with("http://127.0.0.1:"));
+    let profile = load_profile(Some("browser")).unwrap();
+    assert_eq!(profile.access_token.as_deref(), Some("fake-token-1"));
+    assert_eq!(profile.client_secret.as_deref(), Some(CLIENT_SECRET));
+}
+
+#[tokio::test]
+async fn browser_login_rejects_a_redirect_with_another_state() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+
+    let (result, page) = browser_login(&twitch, "forged", true, Some("forged")).await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        "The redirect did not contain the expected state, the login was aborted."
+    );
+    assert_eq!(page, "HTTP/1.1 400 Bad Request");
+    assert_eq!(twitch.state().requests, ["GET /oauth2/authorize"]);
+    assert_eq!(load_profile(Some("forged")).unwrap().access_token, None);
+}
+
+#[tokio::test]
+async fn client_secrets_from_the_env_are_not_stored() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+
+    let (result, _) = browser_login(&twitch, "env-secret", false, None).await;
+    result.unwrap();
+    let profile = load_profile(Some("env-secret")).unwrap();
+    assert_eq!(profile.access_token.as_deref(), Some("fake-token-1"));
+    assert_eq!(profile.client_secret, None);
+
+    // nor by refreshing the token
+    let env = DotEnv {
+        token: "fake-token-1".to_string(),
+        refresh_token: Some("fake-refresh-token-1".to_string()),
+        client_secret: Some(CLIENT_SECRET.to_string()),
+        store_client_secret: false,
+        ..stored_env("env-secret")
+    };
+    let client = twitch.client_for(&env).await.unwrap();
+    let user = client.get_user_id().unwrap().clone();
+    twitch.state().oauth.expired = true;
+    client.get_stream_tags(&user).await.unwrap();
+
+    let profile = load_profile(Some("env-secret")).unwrap();
+    assert_eq!(profile.access_token.as_deref(), Some("fake-token-2"));
+    assert_eq!(profile.client_secret, None);
+}
+
+/// Stores the token of the fake in `profile` of `profiles.toml`
+fn store_token(profile: &str) {
+    save_token(
+        profile,
+        &TokenSource::Profile,
+        TOKEN.to_string(),
+        Some(REFRESH_TOKEN.to_string()),
+        Some(CLIENT_ID.to_string()),
+        None,
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn status_shows_the_user_expiry_and_scopes_of_a_valid_token() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+    store_token("status");
+
+    let status = status(&twitch.http(), Some("status")).await.unwrap();
+
+    assert_eq!(status.profile, "status");
+    assert_eq!(status.login.as_deref(), Some(LOGIN));
+    assert_eq!(status.user_id.as_deref(), Some(USER_ID));
+    assert_eq!(status.client_id, CLIENT_ID);
+    assert_eq!(status.expires_in, Some(3600));
+    assert_eq!(
+        status.scopes,
+        ["channel:manage:broadcast", "channel:manage:redemptions"]
+    );
+    assert!(status.missing_scopes.is_empty());
+}
+
+#[tokio::test]
+async fn status_of_an_expired_token_is_an_error() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth {
+        expired: true,
+        ..Oauth::default()
+    });
+    store_token("expired");
+
+    let error = status(&twitch.http(), Some("expired")).await.unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "The token of profile `expired` is expired or was revoked."
+    );
+    // the token is kept to be refreshed
+    assert_eq!(
+        load_profile(Some("expired"))
+            .unwrap()
+            .access_token
+            .as_deref(),
+        Some(TOKEN)
+    );
+}
+
+#[tokio::test]
+async fn scopes_are_missing_unless_granted_or_implied() {
+    let _home = config_home().await;
+    store_token("scopes");
+    for (granted, missing) in &[
+        (
+            vec!["channel:manage:broadcast", "channel:read:redemptions"],
+            vec!["channel:manage:redemptions"],
+        ),
+        (
+            vec!["user:edit:broadcast", "channel:manage:redemptions"],
+            vec![],
+        ),
+    ] {
+        let twitch = twitch(Oauth {
+            scopes: granted.iter().map(|scope| scope.to_string()).collect(),
+            ..Oauth::default()
+        });
+
+        let status = status(&twitch.http(), Some("scopes")).await.unwrap();
+
+        assert_eq!(status.scopes, *granted);
+        assert_eq!(status.missing_scopes, *missing);
+    }
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_removed_from_the_profile() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth::default());
+    store_token("revoke");
+
+    revoke(&twitch.http(), Some("revoke")).await.unwrap();
+
+    let state = twitch.state();
+    assert_eq!(
+        state.requests,
+        ["GET /oauth2/validate", "POST /oauth2/revoke"]
+    );
+    assert!(state.oauth.revoked);
+    let profile = load_profile(Some("revoke")).unwrap();
+    assert_eq!(profile.access_token, None);
+    assert_eq!(profile.refresh_token, None);
+    assert_eq!(profile.client_id.as_deref(), Some(CLIENT_ID));
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_removed_without_revoking_them() {
+    let _home = config_home().await;
+    let twitch = twitch(Oauth {
+        expired: true,
+        ..Oauth::default()
+    });
+    store_token("invalid");
+
+    revoke(&twitch.http(), Some("invalid")).await.unwrap();
+
+    assert_eq!(twitch.state().requests, ["GET /oauth2/validate"]);
+    assert_eq!(load_profile(Some("invalid")).unwrap().access_token, None);
+}
@@ -0,0 +1,102 @@
+//! Records the requests of a command against the fake Twitch and replays them without it
+
+mod common;
+
+use common::{FakeTwitch, State, TOKEN};
+use std::{fs, sync::Arc, time::Duration};
+use twitchctl::{
+    cache::TagCache,
+    cassette::{Recorder, Replayer},
+    file::handle_file,
+};
+
+const STREAM_FILE: &str = r#"
+title = "Recorded"
+category = "music"
+tags = ["chill"]
+rewards = ["stretch"]
+"#;
+
+#[tokio::test]
+async fn recorded_cassettes_are_redacted_and_replay_the_command() {
+    let dir = common::test_dir("cassette");
+    let file = dir.join("stream.toml");
+    let cassette = dir.join("cassette.json");
+    fs::write(&file, STREAM_FILE).unwrap();
+
+    let twitch = FakeTwitch::new(State::seeded());
+    let recorder = Recorder::new(Arc::new(twitch.clone()), &cassette);
+    let client = common::client_with(Arc::new(recorder)).await.unwrap();
+    handle_file(client, &file, true, &[]).await.unwrap();
+
+    let recorded = fs::read_to_string(&cassette).unwrap();
+    assert!(!recorded.contains(TOKEN));
+    assert!(recorded.contains("[redacted]"));
+    assert_eq!(twitch.state().channel().title, "Recorded");
+
+    let replayer = Replayer::open(&cassette).unwrap();
+    let client = common::client_with(Arc::new(replayer)).await.unwrap();
+    handle_file(client, &file, true, &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn replaying_a_request_that_was_not_recorded_fails() {
+    let dir = common::test_dir("cassette-mismatch");
+    let file = dir.join("stream.toml");
+    let cassette = dir.join("cassette.json");
+    fs::write(&file, "title = \"Recorded\"\n").unwrap();
+
+    let twitch = FakeTwitch::new(State::seeded());
+    let recorder = Recorder::new(Arc::new(twitch), &cassette);
+    let client = common::client_with(Arc::new(recorder)).await.unwrap();
+    handle_file(client, &file, true, &[]).await.unwrap();
+
+    fs::write(&file, "tags = [\"chill\"]\n").unwrap();
+    let replayer = Replayer::open(&cassette).unwrap();
+    let client = common::client_with(Arc::new(replayer)).await.unwrap();
+    let error = handle_file(client, &file, true, &[]).await.unwrap_err();
+
+    assert!(
+        error.to_string().contains("no response left for GET"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn cassettes_recorded_with_a_warm_tag_cache_replay_with_an_empty_one() {
+    let dir = common::test_dir("cassette-tag-cache");
+    let file = dir.join("stream.toml");
+    let cassette = dir.join("cassette.json");
+    fs::write(&file, STREAM_FILE).unwrap();
+    let cache = |name: &str| {
+        Some(TagCache::in_dir(
+            dir.join(name),
+            Duration::from_secs(3600),
+            false,
+        ))
+    };
+
+    let twitch = FakeTwitch::new(State::seeded());
+    let client = twitch.client().await.with_tag_cache(cache("warm"));
+    handle_file(client, &file, true, &[]).await.unwrap();
+    assert!(dir.join("warm").join("tags.json").is_file());
+
+    let recorder = Recorder::new(Arc::new(twitch.clone()), &cassette);
+    let client = common::client_with(Arc::new(recorder))
+        .await
+        .unwrap()
+        .with_tag_cache(cache("warm"));
+    handle_file(client, &file, true, &[]).await.unwrap();
+    assert!(fs::read_to_string(&cassette)
+        .unwrap()
+        .contains("/helix/tags/streams"));
+
+    let replayer = Replayer::open(&cassette).unwrap();
+    let client = common::client_with(Arc::new(replayer))
+        .await
+        .unwrap()
+        .with_tag_cache(cache("empty"));
+    handle_file(client, &file, true, &[]).await.unwrap();
+    assert!(!dir.join("empty").join("tags.json").exists());
+}
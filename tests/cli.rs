@@ -0,0 +1,221 @@
+//! Parses the arguments of twitchctl and runs it to check the exit codes of its errors
+
+mod common;
+
+use serde_json::{json, Value};
+use std::{env, fs, process::Command};
+use structopt::{clap::ErrorKind, StructOpt};
+use twitchctl::{
+    auth::{AuthOptions, AuthSubcommand},
+    cli::{Category, CliOptions},
+    preset::PresetSubcommand,
+};
+
+/// The query and subcommand of `twitchctl preset` with `args`
+fn parse(args: &[&str]) -> Result<(Option<String>, Option<PresetSubcommand>), ErrorKind> {
+    let options = CliOptions::from_iter_safe(["twitchctl", "preset"].iter().chain(args))
+        .map_err(|e| e.kind)?;
+    match options.category {
+        Category::Preset {
+            query, subcommand, ..
+        } => Ok((query, subcommand)),
+        category => panic!("parsed as {:?}", category),
+    }
+}
+
+#[test]
+fn queries_are_applied() {
+    let (query, subcommand) = parse(&["coding"]).unwrap();
+    assert_eq!(query.as_deref(), Some("coding"));
+    assert!(subcommand.is_none());
+}
+
+#[test]
+fn subcommands_shadow_presets_named_like_them() {
+    let (query, subcommand) = parse(&["list"]).unwrap();
+    assert_eq!(query, None);
+    assert!(matches!(
+        subcommand,
+        Some(PresetSubcommand::List { filter: None })
+    ));
+
+    let (query, subcommand) = parse(&["rm", "coding"]).unwrap();
+    assert_eq!(query, None);
+    assert!(matches!(subcommand, Some(PresetSubcommand::Rm { query }) if query == "coding"));
+
+    // unless the name follows `--`
+    let (query, subcommand) = parse(&["--", "list"]).unwrap();
+    assert_eq!(query.as_deref(), Some("list"));
+    assert!(subcommand.is_none());
+}
+
+#[test]
+fn queries_resembling_a_subcommand_are_applied() {
+    let (query, subcommand) = parse(&["shows"]).unwrap();
+    assert_eq!(query.as_deref(), Some("shows"));
+    assert!(subcommand.is_none());
+
+    // anything after the query is refused when the preset is applied
+    let (query, subcommand) = parse(&["coding", "lists"]).unwrap();
+    assert_eq!(query.as_deref(), Some("coding"));
+    assert!(matches!(subcommand, Some(PresetSubcommand::Unexpected(args)) if args == ["lists"]));
+}
+
+#[test]
+fn a_query_or_subcommand_is_required() {
+    assert_eq!(
+        parse(&[]).unwrap_err(),
+        ErrorKind::MissingArgumentOrSubcommand
+    );
+}
+
+#[test]
+fn the_client_secret_is_read_from_the_env() {
+    // only read when parsing the arguments of `auth login`
+    env::set_var("TWITCHCTL_CLIENT_SECRET", "secret-from-env");
+    let options = CliOptions::from_iter_safe(&[
+        "twitchctl",
+        "auth",
+        "login",
+        "--client-id",
+        "client",
+        "--browser",
+    ])
+    .unwrap();
+    match options.category {
+        Category::Auth {
+            options:
+                AuthOptions {
+                    subcommand: AuthSubcommand::Login { client_secret, .. },
+                },
+        } => assert_eq!(client_secret.as_deref(), Some("secret-from-env")),
+        category => panic!("parsed as {:?}", category),
+    }
+}
+
+/// Runs twitchctl in a fresh config folder with the env `vars`, returning its exit code and error output
+fn run(name: &str, vars: &[(&str, &str)], args: &[&str]) -> (i32, String) {
+    let dir = common::test_dir(name);
+    let output = Command::new(env!("CARGO_BIN_EXE_twitchctl"))
+        .args(args)
+        .current_dir(&dir)
+        .env_clear()
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .envs(vars.iter().copied())
+        .output()
+        .expect("twitchctl runs");
+    let code = output.status.code().expect("twitchctl exited");
+    (
+        code,
+        String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    )
+}
+
+/// Replays the responses with their status to the requests `GET url` in order
+fn replay(name: &str, responses: &[(&str, u16, Value)], args: &[&str]) -> (i32, String) {
+    let interactions: Vec<Value> = responses
+        .iter()
+        .map(|(url, status, body)| {
+            json!({
+                "request": { "method": "GET", "url": url, "headers": {}, "body": "" },
+                "response": { "status": status, "headers": {}, "body": body.to_string() },
+            })
+        })
+        .collect();
+    let cassette = common::test_dir(&format!("{}-cassette", name)).join("cassette.json");
+    fs::write(
+        &cassette,
+        json!({ "version": 1, "interactions": interactions }).to_string(),
+    )
+    .unwrap();
+    let mut replay_args = vec!["--replay", cassette.to_str().unwrap()];
+    replay_args.extend(args);
+    run(name, &[], &replay_args)
+}
+
+const VALIDATE: &str = "https://id.twitch.tv/oauth2/validate";
+const SEARCH: &str = "https://api.twitch.tv/helix/search/categories?query=chess&first=20";
+
+/// The answer of `/oauth2/validate` for a token with `scopes`
+fn valid(scopes: &[&str]) -> (&'static str, u16, Value) {
+    (
+        VALIDATE,
+        200,
+        json!({
+            "client_id": common::CLIENT_ID,
+            "login": common::LOGIN,
+            "user_id": common::USER_ID,
+            "scopes": scopes,
+            "expires_in": 3600,
+        }),
+    )
+}
+
+fn error(url: &'static str, status: u16, message: &str) -> (&'static str, u16, Value) {
+    (
+        url,
+        status,
+        json!({ "error": "Error", "status": status, "message": message }),
+    )
+}
+
+#[test]
+fn rejected_tokens_exit_with_3() {
+    let (code, error) = replay(
+        "exit-unauthorized",
+        &[error(VALIDATE, 401, "invalid access token")],
+        &["search", "chess"],
+    );
+    assert_eq!(code, 3, "{}", error);
+    assert_eq!(
+        error,
+        "The token expired and can not be refreshed without a refresh token and client id."
+    );
+}
+
+#[test]
+fn missing_scopes_exit_with_3() {
+    let (code, error) = replay("exit-scopes", &[valid(&[])], &["reward", "list"]);
+    assert_eq!(code, 3, "{}", error);
+    assert!(error.starts_with("The token is missing the scopes `channel:read:redemptions`."));
+}
+
+#[test]
+fn missing_resources_exit_with_2() {
+    let (code, error) = replay(
+        "exit-not-found",
+        &[valid(&[]), error(SEARCH, 404, "no such category")],
+        &["search", "chess"],
+    );
+    assert_eq!(code, 2, "{}", error);
+    assert_eq!(error, "Twitch could not find it: no such category");
+}
+
+#[test]
+fn rate_limited_requests_exit_with_4_once_retries_are_exhausted() {
+    let mut responses = vec![valid(&[])];
+    responses.extend((0..4).map(|_| error(SEARCH, 429, "too many requests")));
+    let (code, error) = replay("exit-rate-limited", &responses, &["search", "chess"]);
+    assert_eq!(code, 4, "{}", error);
+    assert_eq!(
+        error,
+        "Too many requests, Twitch's rate limit was hit. Try again later."
+    );
+}
+
+#[test]
+fn unreachable_twitch_exits_with_5() {
+    // nothing listens on port 9 of the loopback interface
+    let (code, error) = run(
+        "exit-unreachable",
+        &[
+            ("TWITCHCTL_TOKEN", common::TOKEN),
+            ("TWITCHCTL_HELIX_URL", "http://127.0.0.1:9/helix"),
+            ("TWITCHCTL_OAUTH_URL", "http://127.0.0.1:9/oauth2"),
+        ],
+        &["search", "chess"],
+    );
+    assert_eq!(code, 5, "{}", error);
+    assert!(error.starts_with("Could not reach Twitch"), "{}", error);
+}
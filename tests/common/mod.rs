@@ -0,0 +1,857 @@
+//! A fake Twitch for the end-to-end tests
+//!
+//! [`FakeTwitch`] is an [`HttpBackend`] answering the Helix and OAuth requests
+//! of [`ApiClient`] and `auth login` from in-memory state, which the tests
+//! inspect afterwards.
+
+// not every test crate uses all of it
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use twitch_api2::{
+    client::{BoxedFuture, Req, Response},
+    twitch_oauth2::oauth2::url::{form_urlencoded, Url},
+};
+use twitchctl::{
+    config::{DotEnv, Endpoints, TokenSource},
+    http::{HttpBackend, HttpError, TwitchHttp},
+    ApiClient, ApiError,
+};
+
+pub const TOKEN: &str = "fake-token";
+pub const REFRESH_TOKEN: &str = "fake-refresh-token";
+pub const CLIENT_ID: &str = "fake-client";
+pub const CLIENT_SECRET: &str = "fake-secret";
+pub const DEVICE_CODE: &str = "fake-device-code";
+pub const AUTHORIZATION_CODE: &str = "fake-authorization-code";
+pub const USER_ID: &str = "1";
+pub const LOGIN: &str = "tester";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Channel {
+    pub title: String,
+    pub language: String,
+    pub category_id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub id: String,
+    /// the English name, tags with an empty one have no name at all
+    pub name: String,
+    pub is_auto: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reward {
+    pub id: String,
+    pub title: String,
+    pub prompt: String,
+    pub cost: u64,
+    pub background_color: String,
+    pub is_enabled: bool,
+    pub is_user_input_required: bool,
+    pub max_per_stream: Option<u64>,
+    pub max_per_user_per_stream: Option<u64>,
+    pub global_cooldown_seconds: Option<u64>,
+    pub is_paused: bool,
+    pub should_redemptions_skip_request_queue: bool,
+}
+
+impl Reward {
+    pub fn new(id: &str, title: &str, cost: u64, is_enabled: bool) -> Reward {
+        Reward {
+            id: id.to_string(),
+            title: title.to_string(),
+            cost,
+            is_enabled,
+            background_color: "#00E5CB".to_string(),
+            ..Reward::default()
+        }
+    }
+
+    fn to_json(&self, broadcaster_id: &str) -> Value {
+        json!({
+            "broadcaster_id": broadcaster_id,
+            "broadcaster_login": LOGIN,
+            "broadcaster_name": LOGIN,
+            "id": self.id,
+            "title": self.title,
+            "prompt": self.prompt,
+            "cost": self.cost,
+            "image": null,
+            "default_image": null,
+            "background_color": self.background_color,
+            "is_enabled": self.is_enabled,
+            "is_user_input_required": self.is_user_input_required,
+            "max_per_stream_setting": {
+                "is_enabled": self.max_per_stream.is_some(),
+                "max_per_stream": self.max_per_stream.unwrap_or(0),
+            },
+            "max_per_user_per_stream_setting": {
+                "is_enabled": self.max_per_user_per_stream.is_some(),
+                "max_per_user_per_stream": self.max_per_user_per_stream.unwrap_or(0),
+            },
+            "global_cooldown_setting": {
+                "is_enabled": self.global_cooldown_seconds.is_some(),
+                "global_cooldown_seconds": self.global_cooldown_seconds.unwrap_or(0),
+            },
+            "is_paused": self.is_paused,
+            "is_in_stock": true,
+            "should_redemptions_skip_request_queue": self.should_redemptions_skip_request_queue,
+            "redemptions_redeemed_current_stream": null,
+            "cooldown_expires_at": null,
+        })
+    }
+
+    /// Applies the fields set in the body of a create or update request
+    fn apply(&mut self, body: &Value) {
+        let string = |key: &str| body[key].as_str().map(str::to_string);
+        let number = |key: &str| body[key].as_u64();
+        let flag = |key: &str| body[key].as_bool();
+        let setting =
+            |current: Option<u64>, enabled: &str, value: &str| match (flag(enabled), number(value))
+            {
+                (Some(false), _) => None,
+                (_, Some(value)) => Some(value),
+                (Some(true), None) => current.or(Some(0)),
+                (None, None) => current,
+            };
+
+        if let Some(title) = string("title") {
+            self.title = title;
+        }
+        if let Some(prompt) = string("prompt") {
+            self.prompt = prompt;
+        }
+        if let Some(cost) = number("cost") {
+            self.cost = cost;
+        }
+        if let Some(color) = string("background_color") {
+            self.background_color = color;
+        }
+        if let Some(enabled) = flag("is_enabled") {
+            self.is_enabled = enabled;
+        }
+        if let Some(input) = flag("is_user_input_required") {
+            self.is_user_input_required = input;
+        }
+        if let Some(paused) = flag("is_paused") {
+            self.is_paused = paused;
+        }
+        if let Some(skip) = flag("should_redemptions_skip_request_queue") {
+            self.should_redemptions_skip_request_queue = skip;
+        }
+        self.max_per_stream = setting(
+            self.max_per_stream,
+            "is_max_per_stream_enabled",
+            "max_per_stream",
+        );
+        self.max_per_user_per_stream = setting(
+            self.max_per_user_per_stream,
+            "is_max_per_user_per_stream_enabled",
+            "max_per_user_per_stream",
+        );
+        self.global_cooldown_seconds = setting(
+            self.global_cooldown_seconds,
+            "is_global_cooldown_enabled",
+            "global_cooldown_seconds",
+        );
+    }
+}
+
+/// A helix rate limit bucket, refilled every second
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    pub points: u64,
+    remaining: u64,
+    reset: u64,
+}
+
+impl Bucket {
+    pub fn new(points: u64) -> Bucket {
+        Bucket {
+            points,
+            remaining: points,
+            reset: 0,
+        }
+    }
+
+    /// Takes a point for a request, `false` if the bucket is empty
+    fn take(&mut self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the clock is after 1970")
+            .as_secs();
+        if now >= self.reset {
+            self.remaining = self.points;
+            self.reset = now + 1;
+        }
+        if self.remaining == 0 {
+            false
+        } else {
+            self.remaining -= 1;
+            true
+        }
+    }
+}
+
+/// The tokens of the fake id.twitch.tv
+#[derive(Clone, Debug)]
+pub struct Oauth {
+    /// the access token accepted by helix and `/oauth2/validate`
+    pub access_token: String,
+    pub refresh_token: String,
+    /// the access token is rejected until it is refreshed
+    pub expired: bool,
+    /// the tokens were revoked and are rejected, even for refreshing
+    pub revoked: bool,
+    /// the scopes `/oauth2/validate` reports for the access token
+    pub scopes: Vec<String>,
+    /// the errors polling for the token of the device code is answered with, before the token
+    pub pending: Vec<&'static str>,
+    /// how many tokens were issued, the issued ones are numbered
+    pub issued: usize,
+    /// the redirect uri of the last authorization, its code is only exchanged along with it
+    pub redirect_uri: Option<String>,
+}
+
+impl Default for Oauth {
+    fn default() -> Oauth {
+        Oauth {
+            access_token: TOKEN.to_string(),
+            refresh_token: REFRESH_TOKEN.to_string(),
+            expired: false,
+            revoked: false,
+            scopes: vec![
+                "channel:manage:broadcast".to_string(),
+                "channel:manage:redemptions".to_string(),
+            ],
+            pending: vec![],
+            issued: 0,
+            redirect_uri: None,
+        }
+    }
+}
+
+impl Oauth {
+    /// Replaces the tokens with new ones, the old ones are no longer accepted
+    fn issue(&mut self) -> Value {
+        self.issued += 1;
+        self.expired = false;
+        self.revoked = false;
+        self.access_token = format!("{}-{}", TOKEN, self.issued);
+        self.refresh_token = format!("{}-{}", REFRESH_TOKEN, self.issued);
+        json!({
+            "access_token": self.access_token,
+            "refresh_token": self.refresh_token,
+            "expires_in": 3600,
+            "scope": self.scopes,
+            "token_type": "bearer",
+        })
+    }
+
+    /// Whether `token` is the access token and neither expired nor revoked
+    fn accepts(&self, token: Option<&str>) -> bool {
+        !self.expired && !self.revoked && token == Some(self.access_token.as_str())
+    }
+
+    /// Revokes the access token along with its refresh token
+    fn revoke(&mut self, params: &BTreeMap<String, String>) -> (u16, Value) {
+        let field = |key: &str| params.get(key).map(String::as_str);
+        if field("client_id") != Some(CLIENT_ID) {
+            return error(400, "Invalid client");
+        }
+        if field("token") != Some(self.access_token.as_str()) || self.revoked {
+            return error(400, "Invalid token");
+        }
+        self.revoked = true;
+        (200, Value::Null)
+    }
+
+    /// Authorizes right away, redirecting back with the code and the state
+    fn authorize(&mut self, url: &Url) -> (u16, Value) {
+        let query: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+        let field = |key: &str| query.get(key).map(String::as_str);
+        match (
+            field("client_id"),
+            field("response_type"),
+            field("redirect_uri"),
+        ) {
+            (Some(CLIENT_ID), Some("code"), Some(redirect_uri)) => {
+                self.redirect_uri = Some(redirect_uri.to_string());
+                let location = Url::parse_with_params(
+                    redirect_uri,
+                    &[
+                        ("code", AUTHORIZATION_CODE),
+                        ("scope", field("scope").unwrap_or_default()),
+                        ("state", field("state").unwrap_or_default()),
+                    ],
+                )
+                .expect("the redirect uri is a valid url");
+                (302, json!({ "location": location.as_str() }))
+            }
+            _ => error(400, "Invalid authorization request"),
+        }
+    }
+
+    fn token(&mut self, form: &BTreeMap<String, String>) -> (u16, Value) {
+        let field = |key: &str| form.get(key).map(String::as_str);
+        match field("grant_type") {
+            Some("urn:ietf:params:oauth:grant-type:device_code")
+                if field("device_code") == Some(DEVICE_CODE) =>
+            {
+                if self.pending.is_empty() {
+                    (200, self.issue())
+                } else {
+                    error(400, self.pending.remove(0))
+                }
+            }
+            Some("refresh_token")
+                if field("refresh_token") == Some(&self.refresh_token) && !self.revoked =>
+            {
+                (200, self.issue())
+            }
+            Some("refresh_token") => error(400, "Invalid refresh token"),
+            Some("authorization_code")
+                if field("code") == Some(AUTHORIZATION_CODE)
+                    && field("client_secret") == Some(CLIENT_SECRET)
+                    && field("redirect_uri") == self.redirect_uri.as_deref() =>
+            {
+                (200, self.issue())
+            }
+            Some("authorization_code") => error(400, "Invalid authorization code"),
+            Some("client_credentials")
+                if field("client_id") == Some(CLIENT_ID)
+                    && field("client_secret") == Some(CLIENT_SECRET) =>
+            {
+                (200, self.issue())
+            }
+            Some("client_credentials") => error(403, "invalid client secret"),
+            _ => error(400, "Invalid grant"),
+        }
+    }
+}
+
+/// The state of the fake Twitch, keyed by broadcaster id where it belongs to a channel
+#[derive(Clone, Debug, Default)]
+pub struct State {
+    pub users: BTreeMap<String, String>,
+    pub channels: BTreeMap<String, Channel>,
+    pub tags: Vec<Tag>,
+    pub stream_tags: BTreeMap<String, Vec<String>>,
+    pub categories: Vec<Category>,
+    pub rewards: BTreeMap<String, Vec<Reward>>,
+    /// the method and path of every request received, like `PATCH /helix/channels`
+    pub requests: Vec<String>,
+    /// the rate limit of helix requests, unlimited if `None`
+    pub bucket: Option<Bucket>,
+    /// the next request whose method, path and query contain the pattern fails with the status
+    pub failures: Vec<(&'static str, u16)>,
+    /// requests fail like without a network connection
+    pub unreachable: bool,
+    /// how long each request takes to be answered
+    pub latency: Option<Duration>,
+    /// the requests being answered right now
+    pub in_flight: usize,
+    /// the most requests that were answered at the same time
+    pub peak_in_flight: usize,
+    pub oauth: Oauth,
+}
+
+impl State {
+    /// A channel of [`LOGIN`] with some tags, categories and rewards
+    pub fn seeded() -> State {
+        let tag = |id: &str, name: &str, is_auto| Tag {
+            id: id.to_string(),
+            name: name.to_string(),
+            is_auto,
+        };
+        let category = |id: &str, name: &str| Category {
+            id: id.to_string(),
+            name: name.to_string(),
+        };
+        let mut state = State {
+            tags: vec![
+                tag("t-english", "English", true),
+                tag("t-programming", "Programming", false),
+                tag("t-chill", "Chill", false),
+                tag("t-speedrun", "Speedrun", false),
+                tag("t-casual", "Casual Playthrough", false),
+            ],
+            categories: vec![
+                category("509670", "Science & Technology"),
+                category("509658", "Just Chatting"),
+                category("26936", "Music"),
+            ],
+            ..State::default()
+        };
+        state.users.insert(USER_ID.to_string(), LOGIN.to_string());
+        state.users.insert("2".to_string(), "other".to_string());
+        state.channels.insert(
+            USER_ID.to_string(),
+            Channel {
+                title: "Old title".to_string(),
+                language: "en".to_string(),
+                category_id: "509658".to_string(),
+            },
+        );
+        state.stream_tags.insert(
+            USER_ID.to_string(),
+            vec!["t-english".to_string(), "t-chill".to_string()],
+        );
+        state.rewards.insert(
+            USER_ID.to_string(),
+            vec![
+                Reward::new("r-hydrate", "Hydrate", 100, false),
+                Reward::new("r-song", "Song request", 500, true),
+                Reward::new("r-stretch", "Stretch", 300, true),
+            ],
+        );
+        state
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channels[USER_ID]
+    }
+
+    pub fn reward(&self, title: &str) -> &Reward {
+        self.rewards[USER_ID]
+            .iter()
+            .find(|r| r.title == title)
+            .expect("the reward exists")
+    }
+
+    /// The names of the tags of the token's channel
+    pub fn stream_tag_names(&self) -> Vec<&str> {
+        self.stream_tags[USER_ID]
+            .iter()
+            .filter_map(|id| self.tags.iter().find(|t| &t.id == id))
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    fn tag_json(tag: &Tag) -> Value {
+        let names = if tag.name.is_empty() {
+            json!({})
+        } else {
+            json!({ "en-us": tag.name })
+        };
+        json!({
+            "tag_id": tag.id,
+            "is_auto": tag.is_auto,
+            "localization_names": names,
+            "localization_descriptions": { "en-us": format!("{} streams", tag.name) },
+        })
+    }
+
+    /// Answers a request sent with the bearer `token`
+    fn handle(
+        &mut self,
+        method: &str,
+        url: &Url,
+        token: Option<&str>,
+        body: &[u8],
+    ) -> (u16, Value) {
+        let query = |key: &str| -> Vec<String> {
+            url.query_pairs()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v.into_owned())
+                .collect()
+        };
+        let first = |key: &str| query(key).into_iter().next().unwrap_or_default();
+        let form: BTreeMap<String, String> = form_urlencoded::parse(body).into_owned().collect();
+        let body: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+
+        match (method, url.path()) {
+            ("GET", "/oauth2/validate") if self.oauth.accepts(token) => (
+                200,
+                json!({
+                    "client_id": CLIENT_ID,
+                    "login": LOGIN,
+                    "user_id": USER_ID,
+                    "scopes": self.oauth.scopes,
+                    "expires_in": 3600,
+                }),
+            ),
+            ("GET", "/oauth2/validate") => error(401, "invalid access token"),
+            ("POST", "/oauth2/device") => (
+                200,
+                json!({
+                    "device_code": DEVICE_CODE,
+                    "expires_in": 1800,
+                    "interval": 5,
+                    "user_code": "FAKECODE",
+                    "verification_uri": "https://www.twitch.tv/activate",
+                }),
+            ),
+            ("GET", "/oauth2/authorize") => self.oauth.authorize(url),
+            ("POST", "/oauth2/token") => self.oauth.token(&form),
+            // twitch_oauth2 sends the token in the query instead of the form
+            ("POST", "/oauth2/revoke") => {
+                let mut params: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+                params.extend(form);
+                self.oauth.revoke(&params)
+            }
+            ("GET", "/helix/users") => {
+                let logins = query("login");
+                let ids = query("id");
+                let users: Vec<Value> = self
+                    .users
+                    .iter()
+                    .filter(|(id, login)| {
+                        (logins.is_empty() && ids.is_empty())
+                            || logins.contains(login)
+                            || ids.contains(id)
+                    })
+                    .map(|(id, login)| {
+                        json!({
+                            "broadcaster_type": "",
+                            "created_at": "2020-01-01T00:00:00Z",
+                            "description": "",
+                            "display_name": login,
+                            "id": id,
+                            "login": login,
+                            "offline_image_url": "",
+                            "profile_image_url": "",
+                            "type": "",
+                            "view_count": 0,
+                        })
+                    })
+                    .collect();
+                (200, json!({ "data": users }))
+            }
+            ("GET", "/helix/search/categories") => {
+                let term = first("query").to_lowercase();
+                let max = first("first").parse().unwrap_or(20);
+                let categories: Vec<Value> = self
+                    .categories
+                    .iter()
+                    .filter(|c| c.name.to_lowercase().contains(&term))
+                    .take(max)
+                    .map(|c| json!({ "id": c.id, "name": c.name, "box_art_url": "" }))
+                    .collect();
+                (200, json!({ "data": categories }))
+            }
+            ("GET", "/helix/channels") => {
+                let id = first("broadcaster_id");
+                let channel = self.channels.get(&id).cloned().unwrap_or_default();
+                let category = self.categories.iter().find(|c| c.id == channel.category_id);
+                let data = json!({
+                    "broadcaster_id": id,
+                    "broadcaster_name": LOGIN,
+                    "broadcaster_language": channel.language,
+                    "game_id": channel.category_id,
+                    "game_name": category.map(|c| c.name.as_str()).unwrap_or_default(),
+                    "title": channel.title,
+                });
+                (200, json!({ "data": [data] }))
+            }
+            ("PATCH", "/helix/channels") => {
+                let channel = self.channels.entry(first("broadcaster_id")).or_default();
+                if let Some(title) = body["title"].as_str() {
+                    channel.title = title.to_string();
+                }
+                if let Some(language) = body["broadcaster_language"].as_str() {
+                    channel.language = language.to_string();
+                }
+                if let Some(category_id) = body["game_id"].as_str() {
+                    channel.category_id = category_id.to_string();
+                }
+                (204, Value::Null)
+            }
+            ("GET", "/helix/tags/streams") => {
+                let offset: usize = first("after").parse().unwrap_or(0);
+                let page_size = first("first").parse().unwrap_or(20);
+                // small pages to exercise the pagination of the tag catalogue
+                let page_size = page_size.min(2);
+                let page: Vec<Value> = self
+                    .tags
+                    .iter()
+                    .skip(offset)
+                    .take(page_size)
+                    .map(State::tag_json)
+                    .collect();
+                let pagination = if offset + page_size < self.tags.len() {
+                    json!({ "cursor": (offset + page_size).to_string() })
+                } else {
+                    json!({})
+                };
+                (200, json!({ "data": page, "pagination": pagination }))
+            }
+            ("GET", "/helix/streams/tags") => {
+                let tags: Vec<Value> = self
+                    .stream_tags
+                    .get(&first("broadcaster_id"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| self.tags.iter().find(|t| &t.id == id))
+                    .map(State::tag_json)
+                    .collect();
+                (200, json!({ "data": tags }))
+            }
+            ("PUT", "/helix/streams/tags") => {
+                let requested: Vec<String> = body["tag_ids"]
+                    .as_array()
+                    .map(|ids| {
+                        ids.iter()
+                            .filter_map(|id| id.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let is_auto = |id: &String| self.tags.iter().any(|t| &t.id == id && t.is_auto);
+                if let Some(id) = requested.iter().find(|id| is_auto(id)) {
+                    return error(400, &format!("automatic tag {} can not be set", id));
+                }
+                // automatic tags are not affected by replacing the tags
+                let mut tags: Vec<String> = self
+                    .stream_tags
+                    .get(&first("broadcaster_id"))
+                    .into_iter()
+                    .flatten()
+                    .filter(|id| is_auto(id))
+                    .cloned()
+                    .collect();
+                tags.extend(requested);
+                self.stream_tags.insert(first("broadcaster_id"), tags);
+                (204, Value::Null)
+            }
+            ("GET", "/helix/channel_points/custom_rewards") => {
+                let broadcaster_id = first("broadcaster_id");
+                let rewards: Vec<Value> = self
+                    .rewards
+                    .get(&broadcaster_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|r| r.to_json(&broadcaster_id))
+                    .collect();
+                (200, json!({ "data": rewards }))
+            }
+            ("POST", "/helix/channel_points/custom_rewards") => {
+                let broadcaster_id = first("broadcaster_id");
+                let rewards = self.rewards.entry(broadcaster_id.clone()).or_default();
+                let mut reward = Reward::new(&format!("r-{}", rewards.len() + 1), "", 0, true);
+                reward.apply(&body);
+                let data = reward.to_json(&broadcaster_id);
+                rewards.push(reward);
+                (200, json!({ "data": [data] }))
+            }
+            ("PATCH", "/helix/channel_points/custom_rewards") => {
+                let broadcaster_id = first("broadcaster_id");
+                let id = first("id");
+                match self
+                    .rewards
+                    .get_mut(&broadcaster_id)
+                    .into_iter()
+                    .flatten()
+                    .find(|r| r.id == id)
+                {
+                    Some(reward) => {
+                        reward.apply(&body);
+                        (200, json!({ "data": [reward.to_json(&broadcaster_id)] }))
+                    }
+                    None => error(404, "reward not found"),
+                }
+            }
+            ("DELETE", "/helix/channel_points/custom_rewards") => {
+                let id = first("id");
+                let rewards = self.rewards.entry(first("broadcaster_id")).or_default();
+                match rewards.iter().position(|r| r.id == id) {
+                    Some(position) => {
+                        rewards.remove(position);
+                        (204, Value::Null)
+                    }
+                    None => error(404, "reward not found"),
+                }
+            }
+            _ => error(404, "not found"),
+        }
+    }
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (
+        status,
+        json!({ "error": "Error", "status": status, "message": message }),
+    )
+}
+
+/// Serves the requests of [`ApiClient`] from a shared [`State`]
+#[derive(Clone, Default)]
+pub struct FakeTwitch {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeTwitch {
+    pub fn new(state: State) -> FakeTwitch {
+        FakeTwitch {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("no test panicked holding the state")
+    }
+
+    /// The endpoints the client is pointed at, to check they are used instead of Twitch's
+    pub fn endpoints() -> Endpoints {
+        Endpoints {
+            helix_url: "http://fake.twitch/helix".to_string(),
+            oauth_url: "http://fake.twitch/oauth2".to_string(),
+        }
+    }
+
+    /// A client with the tokens of `env` talking to this fake
+    pub async fn client_for(&self, env: &DotEnv) -> Result<ApiClient<'static>, ApiError> {
+        ApiClient::with_http(env, self.http()).await
+    }
+
+    /// The http client of twitchctl, talking to this fake
+    pub fn http(&self) -> TwitchHttp {
+        TwitchHttp::new(Arc::new(self.clone()), FakeTwitch::endpoints())
+    }
+
+    /// A client for the channel of [`LOGIN`] talking to this fake
+    pub async fn client(&self) -> ApiClient<'static> {
+        client_with(Arc::new(self.clone()))
+            .await
+            .expect("the fake accepts the token")
+    }
+
+    fn respond(&self, request: &Req) -> Response {
+        let (status, body) = self.handle(request);
+        let mut response = http::Response::builder().status(status);
+        // redirects are answered with their location in the body
+        if let Some(location) = body["location"].as_str().filter(|_| status == 302) {
+            response = response.header("Location", location);
+        }
+        let body = match body {
+            Value::Null => vec![],
+            body => body.to_string().into_bytes(),
+        };
+        response = response.header("Content-Type", "application/json");
+        if let Some(bucket) = &self.state().bucket {
+            response = response
+                .header("Ratelimit-Limit", bucket.points)
+                .header("Ratelimit-Remaining", bucket.remaining)
+                .header("Ratelimit-Reset", bucket.reset);
+        }
+        response.body(body).expect("the response is valid")
+    }
+
+    fn handle(&self, request: &Req) -> (u16, Value) {
+        let url = Url::parse(&request.uri().to_string()).expect("requests have valid urls");
+        assert_eq!(
+            url.host_str(),
+            Some("fake.twitch"),
+            "request was not sent to the configured endpoints: {}",
+            url
+        );
+
+        let token = request
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|authorization| authorization.split_whitespace().nth(1));
+
+        let mut state = self.state();
+        state
+            .requests
+            .push(format!("{} {}", request.method(), url.path()));
+        // the OAuth endpoints check their credentials themselves
+        let is_helix = url.path().starts_with("/helix/");
+        if is_helix && !state.oauth.accepts(token) {
+            return error(401, "invalid oauth token");
+        }
+
+        if let Some(bucket) = state.bucket.as_mut().filter(|_| is_helix) {
+            if !bucket.take() {
+                return error(429, "too many requests");
+            }
+        }
+        let target = format!(
+            "{} {}?{}",
+            request.method(),
+            url.path(),
+            url.query().unwrap_or("")
+        );
+        if let Some(i) = state
+            .failures
+            .iter()
+            .position(|(pattern, _)| target.contains(pattern))
+        {
+            let (_, status) = state.failures.remove(i);
+            return error(status, "the fake failed as told");
+        }
+        state.handle(request.method().as_str(), &url, token, request.body())
+    }
+}
+
+impl HttpBackend for FakeTwitch {
+    fn send(&self, request: Req) -> BoxedFuture<'_, Result<Response, HttpError>> {
+        if self.state().unreachable {
+            return Box::pin(async { Err(HttpError::Backend("connection refused".into())) });
+        }
+        Box::pin(async move {
+            let latency = {
+                let mut state = self.state();
+                state.in_flight += 1;
+                state.peak_in_flight = state.peak_in_flight.max(state.in_flight);
+                state.latency
+            };
+            if let Some(latency) = latency {
+                tokio::time::sleep(latency).await;
+            }
+            let response = self.respond(&request);
+            self.state().in_flight -= 1;
+            Ok(response)
+        })
+    }
+}
+
+/// The token of [`LOGIN`] from `TWITCHCTL_TOKEN`, without a refresh token
+pub fn env() -> DotEnv {
+    DotEnv {
+        token: TOKEN.to_string(),
+        refresh_token: None,
+        client_id: None,
+        client_secret: None,
+        store_client_secret: false,
+        profile: "test".to_string(),
+        source: TokenSource::Env,
+        locale: None,
+        broadcaster: None,
+        endpoints: FakeTwitch::endpoints(),
+    }
+}
+
+/// A client for the channel of [`LOGIN`] sending its requests to `backend`
+pub async fn client_with(backend: Arc<dyn HttpBackend>) -> Result<ApiClient<'static>, ApiError> {
+    let env = env();
+    let http = TwitchHttp::new(backend, env.endpoints.clone());
+    ApiClient::with_http(&env, http).await
+}
+
+/// A fresh directory for the files of a test
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("twitchctl-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("the temp dir is writable");
+    dir
+}
@@ -0,0 +1,100 @@
+//! Encrypts tokens and picks the token source of a profile
+//!
+//! The sources are read from process-wide variables, so they are tested in a single test.
+
+mod common;
+
+use common::{FakeTwitch, State};
+use std::{env, fs};
+use tokio::time;
+use twitchctl::{
+    auth::login,
+    config::{config_dir, load_env, load_profile, save_token, TokenSource},
+    credentials::{read_encrypted, write_encrypted, Tokens},
+};
+
+#[test]
+fn encrypted_tokens_round_trip() {
+    let file = env::temp_dir()
+        .join(format!("twitchctl-{}-encrypted", std::process::id()))
+        .join("tokens.toml");
+    let tokens = Tokens {
+        access_token: "secret-token".to_string(),
+        refresh_token: Some("secret-refresh".to_string()),
+        client_secret: Some("secret-client".to_string()),
+    };
+
+    write_encrypted(&file, "passphrase", &tokens).unwrap();
+    let content = fs::read_to_string(&file).unwrap();
+    let decrypted = read_encrypted(&file, "passphrase").unwrap();
+    let error = read_encrypted(&file, "wrong").unwrap_err();
+
+    assert!(!content.contains("secret"));
+    assert_eq!(decrypted.access_token, "secret-token");
+    assert_eq!(decrypted.refresh_token.as_deref(), Some("secret-refresh"));
+    assert_eq!(decrypted.client_secret.as_deref(), Some("secret-client"));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Could not decrypt `{}`, is the passphrase correct?",
+            file.display()
+        )
+    );
+}
+
+#[tokio::test]
+async fn token_sources_are_tried_in_order() {
+    let dir = env::temp_dir().join(format!("twitchctl-{}-sources", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    env::set_var("XDG_CONFIG_HOME", &dir);
+    env::set_var("TWITCHCTL_TOKEN", "from-env");
+    let source = |profile| {
+        let env = load_env(profile).unwrap();
+        (env.token, env.source)
+    };
+
+    save_token(
+        "order",
+        &TokenSource::Profile,
+        "from-profile".to_string(),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    assert!(matches!(source(None), (token, TokenSource::Env) if token == "from-env"));
+    assert!(
+        matches!(source(Some("order")), (token, TokenSource::Profile) if token == "from-profile")
+    );
+
+    let tokens = Tokens {
+        access_token: "from-file".to_string(),
+        refresh_token: None,
+        client_secret: None,
+    };
+    let file = config_dir().unwrap().join("tokens").join("order.toml");
+    write_encrypted(&file, "passphrase", &tokens).unwrap();
+    env::set_var("TWITCHCTL_PASSPHRASE", "passphrase");
+    assert!(
+        matches!(source(Some("order")), (token, TokenSource::Encrypted(_)) if token == "from-file")
+    );
+
+    env::set_var("TWITCHCTL_TOKEN_COMMAND", "echo from-command");
+    assert!(
+        matches!(source(Some("order")), (token, TokenSource::Command(None)) if token == "from-command")
+    );
+
+    // a failing command falls through to the next source
+    env::set_var("TWITCHCTL_TOKEN_COMMAND", "exit 1");
+    assert!(
+        matches!(source(Some("order")), (token, TokenSource::Encrypted(_)) if token == "from-file")
+    );
+
+    // logging in with a token command does not store the tokens in the profile
+    time::pause();
+    let twitch = FakeTwitch::new(State::seeded());
+    login(&twitch.http(), "command", common::CLIENT_ID, None, false)
+        .await
+        .unwrap();
+    assert_eq!(load_profile(Some("command")).unwrap().access_token, None);
+}
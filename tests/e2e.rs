@@ -0,0 +1,281 @@
+//! Runs twitchctl's commands against the fake Twitch in `common` and checks the channel afterwards
+
+mod common;
+
+use common::{FakeTwitch, Oauth, State};
+use std::{env, fs, path::PathBuf, sync::Once};
+use structopt::StructOpt;
+use twitchctl::{
+    config::AppEnv,
+    file::handle_file,
+    preset::handle_preset,
+    rewards::{rewards, RewardsSubcommand},
+    tags::{tags, TagsSubcommand},
+    ApiClient, ApiError,
+};
+
+/// The config folder, shared by all tests of this file as it is found through the env
+///
+/// Every test that may look up the config folder, like files extending presets,
+/// calls this first, so the env var is set before it is read.
+fn config_home() -> PathBuf {
+    static CONFIG_HOME: Once = Once::new();
+    let config = env::temp_dir().join(format!("twitchctl-{}-e2e", std::process::id()));
+    CONFIG_HOME.call_once(|| {
+        let _ = fs::remove_dir_all(&config);
+        env::set_var("XDG_CONFIG_HOME", &config);
+    });
+    config
+}
+
+#[tokio::test]
+async fn file_applies_channel_info_tags_and_rewards() {
+    config_home();
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("file").join("stream.toml");
+    fs::write(
+        &file,
+        r#"
+title = "Writing tests"
+category = "science"
+language = "de"
+tags = ["programming", "Speedrun"]
+rewards = ["hydrate", "Stretch"]
+"#,
+    )
+    .unwrap();
+
+    handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Writing tests");
+    assert_eq!(state.channel().language, "de");
+    assert_eq!(state.channel().category_id, "509670");
+    assert_eq!(
+        state.stream_tag_names(),
+        ["English", "Programming", "Speedrun"]
+    );
+    assert!(state.reward("Hydrate").is_enabled);
+    assert!(state.reward("Stretch").is_enabled);
+    assert!(!state.reward("Song request").is_enabled);
+}
+
+#[tokio::test]
+async fn file_only_changes_what_it_configures() {
+    config_home();
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("partial").join("stream.yml");
+    fs::write(&file, "title: Only the title\n").unwrap();
+
+    handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Only the title");
+    assert_eq!(state.channel().category_id, "509658");
+    assert_eq!(state.stream_tag_names(), ["English", "Chill"]);
+    assert!(!state.reward("Hydrate").is_enabled);
+    assert!(state.reward("Song request").is_enabled);
+    assert!(!state
+        .requests
+        .iter()
+        .any(|r| r.contains("streams/tags") || r.contains("custom_rewards")));
+}
+
+#[tokio::test]
+async fn file_with_unknown_category_fails() {
+    config_home();
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("unknown-category").join("stream.toml");
+    fs::write(&file, "category = \"Basket Weaving\"\n").unwrap();
+
+    let error = handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Could not find a category for `Basket Weaving`"
+    );
+    assert_eq!(twitch.state().channel().category_id, "509658");
+}
+
+#[tokio::test]
+async fn file_in_dry_run_changes_nothing() {
+    config_home();
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("dry-run").join("stream.toml");
+    fs::write(
+        &file,
+        "title = \"Not applied\"\ntags = [\"speedrun\"]\nrewards = [\"hydrate\"]\n",
+    )
+    .unwrap();
+
+    handle_file(twitch.client().await.with_dry_run(true), &file, true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Old title");
+    assert_eq!(state.stream_tag_names(), ["English", "Chill"]);
+    assert!(!state.reward("Hydrate").is_enabled);
+    assert!(state.requests.iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn file_needing_missing_scopes_fails_before_changing_anything() {
+    config_home();
+    let twitch = FakeTwitch::new(State {
+        oauth: Oauth {
+            scopes: vec!["channel:manage:broadcast".to_string()],
+            ..Oauth::default()
+        },
+        ..State::seeded()
+    });
+    let file = common::test_dir("scopes").join("stream.toml");
+    fs::write(&file, "title = \"Hydrated\"\nrewards = [\"hydrate\"]\n").unwrap();
+
+    let error = handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(ApiError::MissingScopes(_))
+    ));
+    assert_eq!(
+        error.to_string(),
+        "The token is missing the scopes `channel:manage:redemptions`.\n\
+        Run `twitchctl auth login` to get a token with all needed scopes."
+    );
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Old title");
+    assert!(state.requests.iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn file_is_refused_with_an_app_token_before_changing_anything() {
+    config_home();
+    let twitch = FakeTwitch::new(State::seeded());
+    let env = AppEnv {
+        client_id: common::CLIENT_ID.to_string(),
+        client_secret: common::CLIENT_SECRET.to_string(),
+        profile: "test".to_string(),
+        locale: None,
+        broadcaster: Some(common::LOGIN.to_string()),
+        endpoints: FakeTwitch::endpoints(),
+    };
+    let client = ApiClient::with_app_http(&env, twitch.http()).await.unwrap();
+    let file = common::test_dir("app-token").join("stream.toml");
+    fs::write(&file, "title = \"Hydrated\"\n").unwrap();
+
+    let error = handle_file(client, &file, true, &[]).await.unwrap_err();
+
+    assert!(matches!(error.downcast_ref(), Some(ApiError::AppToken)));
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Old title");
+    assert_eq!(state.requests[0], "POST /oauth2/token");
+    assert!(state.requests[1..].iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn preset_is_found_by_fuzzy_query() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let presets = config_home().join("twitchctl").join("presets");
+    fs::create_dir_all(&presets).unwrap();
+    fs::write(
+        presets.join("music.toml"),
+        "title = \"Making music\"\ncategory = \"Music\"\n",
+    )
+    .unwrap();
+    fs::write(
+        presets.join("coding.yml"),
+        "title: Coding\ncategory: Science & Technology\ntags: [Programming]\n",
+    )
+    .unwrap();
+
+    handle_preset(twitch.client().await, "cod", true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Coding");
+    assert_eq!(state.channel().category_id, "509670");
+    assert_eq!(state.stream_tag_names(), ["English", "Programming"]);
+}
+
+#[tokio::test]
+async fn tags_set_replaces_the_tags() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let command = TagsSubcommand::from_iter(&["tags", "set", "speedrun", "casual playthrough"]);
+
+    tags(twitch.client().await, "en-us", command).await.unwrap();
+
+    assert_eq!(
+        twitch.state().stream_tag_names(),
+        ["English", "Speedrun", "Casual Playthrough"]
+    );
+}
+
+#[tokio::test]
+async fn tags_set_add_keeps_the_current_tags() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let command = TagsSubcommand::from_iter(&["tags", "set", "--add", "programming"]);
+
+    tags(twitch.client().await, "en-us", command).await.unwrap();
+
+    assert_eq!(
+        twitch.state().stream_tag_names(),
+        ["English", "Chill", "Programming"]
+    );
+}
+
+#[tokio::test]
+async fn rewards_update_changes_the_matching_reward() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let command = RewardsSubcommand::from_iter(&[
+        "rewards",
+        "update",
+        "song",
+        "--cost",
+        "750",
+        "--prompt",
+        "Which song?",
+        "--user-input",
+        "--cooldown",
+        "60",
+        "--disabled",
+    ]);
+
+    rewards(twitch.client().await, command).await.unwrap();
+
+    let state = twitch.state();
+    let reward = state.reward("Song request");
+    assert_eq!(reward.cost, 750);
+    assert_eq!(reward.prompt, "Which song?");
+    assert!(reward.is_user_input_required);
+    assert_eq!(reward.global_cooldown_seconds, Some(60));
+    assert!(!reward.is_enabled);
+    assert_eq!(state.reward("Stretch").cost, 300);
+}
+
+#[tokio::test]
+async fn rewards_update_without_a_unique_match_fails() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let command = RewardsSubcommand::from_iter(&["rewards", "update", "e", "--cost", "1"]);
+
+    let error = rewards(twitch.client().await, command).await.unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Did not find a unique reward matching `e`"
+    );
+    assert!(!twitch
+        .state()
+        .requests
+        .iter()
+        .any(|r| r.starts_with("PATCH")));
+}
@@ -0,0 +1,158 @@
+//! Reads and writes config files in every supported format
+
+mod common;
+
+use std::{fs, path::PathBuf};
+use twitchctl::{
+    file::{config_to_string, load_config, valid_extension, Config},
+    rewards::{RewardConfig, RewardDefinition},
+};
+
+#[test]
+fn ini_has_sections_and_repeated_keys() {
+    let file = common::test_dir("ini").join("stream.ini");
+    fs::write(
+        &file,
+        r#"; a comment
+title = "  Speedrunning, any%  "
+tags = Speedrun
+tags = English
+rewards = Stretch
+
+# another comment
+[rewards]
+title = Hydrate
+cost = 200
+user_input = false
+
+[rewards]
+title = Posture check
+"#,
+    )
+    .unwrap();
+
+    let config = load_config(&file, true).unwrap();
+
+    assert_eq!(config.title.as_deref(), Some("  Speedrunning, any%  "));
+    assert_eq!(
+        config.tags,
+        Some(vec!["Speedrun".to_string(), "English".to_string()])
+    );
+    assert_eq!(
+        config.rewards,
+        Some(vec![
+            RewardConfig::Title("Stretch".to_string()),
+            RewardConfig::Definition(RewardDefinition {
+                title: "Hydrate".to_string(),
+                cost: Some(200),
+                user_input: Some(false),
+                ..RewardDefinition::default()
+            }),
+            RewardConfig::Definition(RewardDefinition {
+                title: "Posture check".to_string(),
+                ..RewardDefinition::default()
+            }),
+        ])
+    );
+}
+
+#[test]
+fn ini_values_are_typed_by_their_field() {
+    let file = common::test_dir("ini-types").join("stream.ini");
+    fs::write(&file, "title = 2021\ntags = 100%\n").unwrap();
+
+    let config = load_config(&file, true).unwrap();
+
+    assert_eq!(config.title.as_deref(), Some("2021"));
+    assert_eq!(config.tags, Some(vec!["100%".to_string()]));
+}
+
+#[test]
+fn errors_have_line_and_column() {
+    let dir = common::test_dir("errors");
+    let error = |name: &str, content: &str| {
+        let file = dir.join(name);
+        fs::write(&file, content).unwrap();
+        load_config(&file, true).unwrap_err().to_string()
+    };
+
+    assert!(error(
+        "cost.ini",
+        "title = Live\n\n[rewards]\ntitle = Hydrate\ncost =  lots\n"
+    )
+    .contains("invalid value `lots`: invalid digit found in string at line 5 column 9"));
+    assert!(error("key.ini", "title = Live\ncategory\n")
+        .contains("expected `=` after the key at line 2 column 9"));
+    assert_eq!(
+        error("section.ini", "[rewards\n"),
+        format!(
+            "Failed to parse configuration: expected `]` at line 1 column 9 in `{}`",
+            dir.join("section.ini").display()
+        )
+    );
+    assert!(error("missing.ini", "\n  [rewards]\n  cost = 1\n")
+        .contains("missing field `title` at line 2 column 3"));
+    // JSON is parsed strictly, not as YAML
+    assert!(error("stream.json", "{\n  \"title\": \"Live\",\n}\n").contains("line 3 column 1"));
+    assert!(error("yaml.json", "title: Live\n").contains("at line 1 column"));
+    assert!(error("stream.toml", "title = \"Live\"\ntags = [\n").contains("line 3 column 1"));
+    assert!(error("stream.yaml", "title: Live\ntags: [a\n").contains("line 3 column 1"));
+}
+
+#[test]
+fn every_format_is_read_back_as_written() {
+    let dir = common::test_dir("formats");
+    let config = Config {
+        title: Some(" Quoted\" ".to_string()),
+        category: Some("Just Chatting".to_string()),
+        tags: Some(vec![]),
+        rewards: Some(vec![RewardConfig::Definition(RewardDefinition {
+            title: "Hydrate".to_string(),
+            cost: Some(200),
+            auto_fulfill: Some(true),
+            ..RewardDefinition::default()
+        })]),
+        ..Config::default()
+    };
+
+    for format in &["ini", "toml", "yaml", "yml", "json"] {
+        let file = dir.join(format!("stream.{}", format));
+        fs::write(&file, config_to_string(&config, format).unwrap()).unwrap();
+        let read = load_config(&file, true).unwrap();
+
+        assert_eq!(read.title, config.title, "{}", format);
+        assert_eq!(read.category, config.category, "{}", format);
+        assert_eq!(read.tags, config.tags, "{}", format);
+        assert_eq!(read.rewards, config.rewards, "{}", format);
+    }
+}
+
+#[test]
+fn ini_lists_are_repeated_keys_and_sections() {
+    let config = Config {
+        title: Some("Live".to_string()),
+        tags: Some(vec!["Chill".to_string(), "English".to_string()]),
+        rewards: Some(vec![RewardConfig::Definition(RewardDefinition {
+            title: "Hydrate".to_string(),
+            cost: Some(200),
+            ..RewardDefinition::default()
+        })]),
+        ..Config::default()
+    };
+
+    assert_eq!(
+        config_to_string(&config, "ini").unwrap(),
+        "title = Live\ntags = Chill\ntags = English\n\n[rewards]\ntitle = Hydrate\ncost = 200\n"
+    );
+}
+
+#[test]
+fn yaml_files_are_config_files() {
+    for extension in &["ini", "toml", "yaml", "yml", "json"] {
+        assert!(valid_extension(&PathBuf::from(format!(
+            "preset.{}",
+            extension
+        ))));
+    }
+    assert!(!valid_extension(&PathBuf::from("preset.txt")));
+}
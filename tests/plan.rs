@@ -0,0 +1,302 @@
+//! Compares config files with the channel of the fake Twitch and applies only the differences
+
+mod common;
+
+use common::{FakeTwitch, Oauth, State};
+use std::fs;
+use twitchctl::{
+    file::load_config,
+    plan::{handle_apply, handle_plan, Plan},
+    ApiError,
+};
+
+#[tokio::test]
+async fn plan_lists_the_changes_without_making_them() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("plan").join("stream.toml");
+    fs::write(
+        &file,
+        r#"
+title = "Writing tests"
+category = "science"
+language = "en"
+tags = ["programming", "chill"]
+rewards = ["hydrate", "stretch"]
+"#,
+    )
+    .unwrap();
+    let client = twitch.client().await;
+
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        plan.render(false),
+        "~ title: \"Old title\" -> \"Writing tests\"\n\
+        ~ category: Just Chatting -> Science & Technology\n\
+        + tag Programming\n\
+        + reward Hydrate\n\
+        - reward Song request"
+    );
+    assert!(twitch.state().requests.iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn tags_named_like_no_tag_are_reported() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("plan-unknown-tags").join("stream.toml");
+    fs::write(&file, "tags = [\"programming\", \"chil\"]\n").unwrap();
+    let client = twitch.client().await;
+
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(plan.unknown_tags, ["chil"]);
+    assert_eq!(plan.render(false), "+ tag Programming\n- tag Chill");
+}
+
+#[tokio::test]
+async fn applying_makes_only_the_necessary_calls() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("apply").join("stream.toml");
+    fs::write(
+        &file,
+        "title = \"Old title\"\ntags = [\"chill\"]\nrewards = [\"stretch\"]\n",
+    )
+    .unwrap();
+    let client = twitch.client().await;
+    let config = load_config(&file, true).unwrap();
+
+    Plan::new(&client, &config)
+        .await
+        .unwrap()
+        .apply(&client)
+        .await
+        .unwrap();
+
+    let requests: Vec<_> = twitch
+        .state()
+        .requests
+        .iter()
+        .filter(|r| !r.starts_with("GET"))
+        .cloned()
+        .collect();
+    assert_eq!(requests, ["PATCH /helix/channel_points/custom_rewards"]);
+    assert!(!twitch.state().reward("Song request").is_enabled);
+    assert!(Plan::new(&client, &config).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn defined_rewards_are_created_or_updated() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("reward-definitions").join("stream.toml");
+    fs::write(
+        &file,
+        r#"
+[[rewards]]
+title = "Hydrate"
+cost = 200
+cooldown = 60
+
+[[rewards]]
+title = "Dance"
+cost = 10
+prompt = "Show your moves"
+user_input = true
+"#,
+    )
+    .unwrap();
+    let client = twitch.client().await;
+    let config = load_config(&file, true).unwrap();
+
+    let plan = Plan::new(&client, &config).await.unwrap();
+    assert_eq!(
+        plan.render(false),
+        "- reward Song request\n\
+        - reward Stretch\n\
+        + reward Dance (new)\n\
+        ~ reward Hydrate: cost 100 -> 200, cooldown 0 -> 60, enabled"
+    );
+    plan.apply(&client).await.unwrap();
+
+    {
+        let state = twitch.state();
+        let hydrate = state.reward("Hydrate");
+        assert!(hydrate.is_enabled);
+        assert_eq!(hydrate.cost, 200);
+        assert_eq!(hydrate.global_cooldown_seconds, Some(60));
+        let dance = state.reward("Dance");
+        assert!(dance.is_enabled);
+        assert_eq!(dance.cost, 10);
+        assert_eq!(dance.prompt, "Show your moves");
+        assert!(dance.is_user_input_required);
+        assert!(!state.reward("Stretch").is_enabled);
+    }
+    assert!(Plan::new(&client, &config).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn titles_and_definitions_can_be_mixed() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let dir = common::test_dir("reward-mixed");
+    let file = dir.join("stream.yml");
+    fs::write(
+        &file,
+        "rewards:\n  - song\n  - title: Stretch\n    max_per_user: 2\n",
+    )
+    .unwrap();
+    let client = twitch.client().await;
+
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(plan.render(false), "~ reward Stretch: max per user 0 -> 2");
+
+    let file = dir.join("missing.yml");
+    fs::write(&file, "rewards:\n  - title: Dance\n").unwrap();
+    let error = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "The reward `Dance` does not exist yet and needs a cost to be created."
+    );
+}
+
+#[tokio::test]
+async fn failed_apply_restores_what_was_changed() {
+    let twitch = FakeTwitch::new(State {
+        failures: vec![("id=r-stretch", 400)],
+        ..State::seeded()
+    });
+    let file = common::test_dir("rollback").join("stream.toml");
+    fs::write(
+        &file,
+        r#"
+title = "Half applied"
+category = "music"
+tags = ["speedrun"]
+rewards = ["hydrate", { title = "Dance", cost = 10 }]
+"#,
+    )
+    .unwrap();
+    let client = twitch.client().await;
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+
+    let error = plan.apply(&client).await.unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Could not apply the config, disabling reward `Stretch` failed: \
+        Twitch rejected the request: the fake failed as told\n\
+        Restored the changes already made:\n  \
+        disabling reward `Song request`\n  \
+        enabling reward `Hydrate`\n  \
+        creating reward `Dance`\n  \
+        setting tags\n  \
+        changing the channel information"
+    );
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Old title");
+    assert_eq!(state.channel().category_id, "509658");
+    assert_eq!(state.stream_tag_names(), ["English", "Chill"]);
+    assert!(!state.reward("Hydrate").is_enabled);
+    assert!(state.reward("Song request").is_enabled);
+    assert!(state.reward("Stretch").is_enabled);
+    let titles: Vec<_> = state.rewards["1"]
+        .iter()
+        .map(|r| r.title.as_str())
+        .collect();
+    assert_eq!(titles, ["Hydrate", "Song request", "Stretch"]);
+}
+
+#[tokio::test]
+async fn notification_is_shown_after_the_title_and_checked_first() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let dir = common::test_dir("notification");
+    let file = dir.join("stream.toml");
+    fs::write(
+        &file,
+        "title = \"Live\"\nnotification = \"Come hang out\"\ncategory = \"music\"\n",
+    )
+    .unwrap();
+    let client = twitch.client().await;
+
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        plan.render(false),
+        "~ title: \"Old title\" -> \"Live\"\n\
+        ! Twitch's API can not set the go-live notification, \
+        set it to \"Come hang out\" in the dashboard.\n\
+        ~ category: Just Chatting -> Music"
+    );
+
+    // the notification alone changes nothing
+    let file = dir.join("unchanged.toml");
+    fs::write(
+        &file,
+        "title = \"Old title\"\nnotification = \"Come hang out\"\n",
+    )
+    .unwrap();
+    let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap();
+    assert!(plan.is_empty());
+    assert_eq!(
+        plan.render(false),
+        "! Twitch's API can not set the go-live notification, \
+        set it to \"Come hang out\" in the dashboard."
+    );
+
+    let file = dir.join("long.toml");
+    fs::write(
+        &file,
+        format!("title = \"Live\"\nnotification = \"{}\"\n", "a".repeat(141)),
+    )
+    .unwrap();
+    let sent = twitch.state().requests.len();
+    let error = Plan::new(&client, &load_config(&file, true).unwrap())
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "The notification has 141 characters, Twitch allows at most 140."
+    );
+    assert_eq!(twitch.state().requests.len(), sent);
+}
+
+#[tokio::test]
+async fn plan_needs_only_the_scopes_to_read_the_channel() {
+    let twitch = FakeTwitch::new(State {
+        oauth: Oauth {
+            scopes: vec!["channel:read:redemptions".to_string()],
+            ..Oauth::default()
+        },
+        ..State::seeded()
+    });
+    let file = common::test_dir("read-scopes").join("stream.toml");
+    fs::write(&file, "title = \"Hydrated\"\nrewards = [\"hydrate\"]\n").unwrap();
+
+    handle_plan(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap();
+    let error = handle_apply(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(ApiError::MissingScopes(scopes)) if scopes.len() == 2
+    ));
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Old title");
+    assert!(state.requests.iter().all(|r| r.starts_with("GET")));
+}
@@ -0,0 +1,425 @@
+//! Resolves presets extending other presets and manages them with the preset subcommands
+
+mod common;
+
+use ansi_term::{
+    Colour::{Blue, Green, Purple, Yellow},
+    Style,
+};
+use common::{FakeTwitch, State};
+use std::{env, fs, path::PathBuf, sync::Once};
+use structopt::StructOpt;
+use twitchctl::{
+    file::{load_config, read_config, resolve_config},
+    preset::{highlight, preset, summaries, PresetSubcommand},
+    rewards::{RewardConfig, RewardDefinition},
+};
+
+/// The presets folder, shared by all tests of this file as the config dir is found through the env
+fn presets() -> PathBuf {
+    static CONFIG_DIR: Once = Once::new();
+    let config = env::temp_dir().join(format!("twitchctl-{}-presets", std::process::id()));
+    CONFIG_DIR.call_once(|| {
+        let _ = fs::remove_dir_all(&config);
+        env::set_var("XDG_CONFIG_HOME", &config);
+    });
+    let presets = config.join("twitchctl").join("presets");
+    fs::create_dir_all(&presets).unwrap();
+    presets
+}
+
+fn title(title: &str) -> RewardConfig {
+    RewardConfig::Title(title.to_string())
+}
+
+#[test]
+fn extended_presets_are_merged_in_order() {
+    let presets = presets();
+    fs::write(
+        presets.join("base.toml"),
+        "language = \"en\"\ntitle = \"Streaming\"\ntags = [\"English\", \"Chill\"]\nrewards = [\"Hydrate\", \"Stretch\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        presets.join("database.yaml"),
+        "extends: [base]\ncategory: Science & Technology\ntags: [Programming, chill]\n",
+    )
+    .unwrap();
+    let file = common::test_dir("extends").join("stream.ini");
+    fs::write(
+        &file,
+        "extends = database\ntitle = Coding\n\n[rewards]\ntitle = hydrate\ncost = 100\n",
+    )
+    .unwrap();
+
+    let config = load_config(&file, true).unwrap();
+
+    assert_eq!(config.title.as_deref(), Some("Coding"));
+    assert_eq!(config.category.as_deref(), Some("Science & Technology"));
+    assert_eq!(config.language.as_deref(), Some("en"));
+    assert_eq!(
+        config.tags,
+        Some(vec![
+            "English".to_string(),
+            "Programming".to_string(),
+            "chill".to_string()
+        ])
+    );
+    assert_eq!(
+        config.rewards,
+        Some(vec![
+            title("Stretch"),
+            RewardConfig::Definition(RewardDefinition {
+                title: "hydrate".to_string(),
+                cost: Some(100),
+                ..RewardDefinition::default()
+            })
+        ])
+    );
+    assert_eq!(config.extends, None);
+}
+
+#[test]
+fn replaced_lists_are_not_appended() {
+    let presets = presets();
+    fs::write(
+        presets.join("replaced-base.toml"),
+        "tags = [\"English\"]\nrewards = [\"Hydrate\"]\n",
+    )
+    .unwrap();
+    let file = common::test_dir("replace").join("stream.toml");
+    fs::write(
+        &file,
+        "extends = [\"replaced-base\"]\nreplace = [\"tags\"]\ntags = [\"Speedrun\"]\nrewards = [\"Stretch\"]\n",
+    )
+    .unwrap();
+
+    let config = resolve_config(&file).unwrap();
+
+    assert_eq!(config.tags, Some(vec!["Speedrun".to_string()]));
+    assert_eq!(
+        config.rewards,
+        Some(vec![title("Hydrate"), title("Stretch")])
+    );
+
+    fs::write(
+        &file,
+        "extends = [\"replaced-base\"]\nreplace = [\"title\"]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        resolve_config(&file).unwrap_err().to_string(),
+        "Only `tags` and `rewards` can be replaced, not `title`."
+    );
+}
+
+#[test]
+fn tags_of_presets_in_other_locales_are_not_appended() {
+    let presets = presets();
+    fs::write(
+        presets.join("german.toml"),
+        "config_locale = \"de-de\"\ntags = [\"Deutsch\"]\n",
+    )
+    .unwrap();
+    let file = common::test_dir("locales").join("stream.toml");
+    fs::write(&file, "extends = [\"german\"]\ntags = [\"Chill\"]\n").unwrap();
+
+    assert_eq!(
+        resolve_config(&file).unwrap_err().to_string(),
+        "The tags of the config and the presets it extends are named in different locales, \
+        `de-de` and the locale of the profile.\n\
+        Set the same `config_locale` in all of them or replace the tags."
+    );
+
+    // replaced tags keep the locale of the config
+    fs::write(
+        &file,
+        "extends = [\"german\"]\nreplace = [\"tags\"]\ntags = [\"Chill\"]\n",
+    )
+    .unwrap();
+    let config = resolve_config(&file).unwrap();
+    assert_eq!(config.tags, Some(vec!["Chill".to_string()]));
+    assert_eq!(config.config_locale, None);
+
+    // a config without tags keeps the locale of the preset's tags
+    fs::write(
+        &file,
+        "extends = [\"german\"]\nconfig_locale = \"en-us\"\ntitle = \"Live\"\n",
+    )
+    .unwrap();
+    let config = resolve_config(&file).unwrap();
+    assert_eq!(config.tags, Some(vec!["Deutsch".to_string()]));
+    assert_eq!(config.config_locale.as_deref(), Some("de-de"));
+
+    fs::write(
+        &file,
+        "extends = [\"german\"]\nconfig_locale = \"de-de\"\ntags = [\"Chill\"]\n",
+    )
+    .unwrap();
+    let config = resolve_config(&file).unwrap();
+    assert_eq!(
+        config.tags,
+        Some(vec!["Deutsch".to_string(), "Chill".to_string()])
+    );
+}
+
+#[test]
+fn cycles_and_missing_presets_are_errors() {
+    let presets = presets();
+    fs::write(presets.join("cycle-a.toml"), "extends = [\"cycle-b\"]\n").unwrap();
+    fs::write(presets.join("cycle-b.yml"), "extends: [cycle-c]\n").unwrap();
+    fs::write(
+        presets.join("cycle-c.json"),
+        "{\"extends\": [\"cycle-a\"]}\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        resolve_config(&presets.join("cycle-a.toml"))
+            .unwrap_err()
+            .to_string(),
+        "The presets extend each other in a cycle: cycle-a.toml -> cycle-b.yml -> cycle-c.json -> cycle-a.toml"
+    );
+
+    let file = common::test_dir("missing").join("stream.toml");
+    fs::write(&file, "extends = [\"xyzzy\"]\n").unwrap();
+    assert_eq!(
+        resolve_config(&file).unwrap_err().to_string(),
+        "Could not find the preset `xyzzy` to extend: No matching presets found."
+    );
+}
+
+fn command(args: &[&str]) -> PresetSubcommand {
+    PresetSubcommand::from_iter(std::iter::once(&"preset").chain(args))
+}
+
+#[tokio::test]
+async fn presets_are_created_listed_renamed_and_removed() {
+    let presets = presets();
+    let run = |args: &[&str]| preset(None, command(args));
+
+    run(&[
+        "new",
+        "zqx-one",
+        "--title",
+        "One",
+        "--category",
+        "Music",
+        "--tag",
+        "Chill",
+        "--tag",
+        "English",
+    ])
+    .await
+    .unwrap();
+    run(&[
+        "new",
+        "zqx-two.yaml",
+        "--extends",
+        "zqx-one",
+        "--title",
+        "Two",
+    ])
+    .await
+    .unwrap();
+    let config = read_config(&presets.join("zqx-one.toml")).unwrap();
+    assert_eq!(config.title.as_deref(), Some("One"));
+    assert_eq!(
+        config.tags,
+        Some(vec!["Chill".to_string(), "English".to_string()])
+    );
+    assert_eq!(
+        run(&["new", "zqx-one"]).await.unwrap_err().to_string(),
+        "The preset `zqx-one.toml` already exists."
+    );
+    assert_eq!(
+        run(&["new", "zqx-channel", "--from-channel"])
+            .await
+            .unwrap_err()
+            .to_string(),
+        "`new --from-channel` needs a connection to Twitch."
+    );
+    assert!(!presets.join("zqx-channel.toml").exists());
+    assert_eq!(
+        summaries(Some("zqx")).unwrap(),
+        ["zqx-one.toml  One [Music]", "zqx-two.yaml  Two [Music]"]
+    );
+
+    run(&["mv", "zqx-on", "zqx-base"]).await.unwrap();
+    assert!(!presets.join("zqx-one.toml").exists());
+    assert_eq!(
+        summaries(Some("zqx")).unwrap(),
+        [
+            "zqx-base.toml  One [Music]",
+            "zqx-two.yaml   invalid: Could not find the preset `zqx-one` to extend: No matching presets found."
+        ]
+    );
+
+    // only renamed, the comments of the same format are kept
+    let two = presets.join("zqx-two.yaml");
+    let commented = format!("# second\n{}", fs::read_to_string(&two).unwrap());
+    fs::write(&two, &commented).unwrap();
+    run(&["mv", "zqx-two", "zqx-two.yml"]).await.unwrap();
+    assert!(!two.exists());
+    assert_eq!(
+        fs::read_to_string(presets.join("zqx-two.yml")).unwrap(),
+        commented
+    );
+    run(&["mv", "zqx-two", "zqx-two.yaml"]).await.unwrap();
+
+    run(&["mv", "zqx-base", "zqx-base.json"]).await.unwrap();
+    assert!(!presets.join("zqx-base.toml").exists());
+    let converted = read_config(&presets.join("zqx-base.json")).unwrap();
+    assert_eq!(converted.title.as_deref(), Some("One"));
+    assert_eq!(converted.category.as_deref(), Some("Music"));
+    assert_eq!(
+        converted.tags,
+        Some(vec!["Chill".to_string(), "English".to_string()])
+    );
+    for (name, error) in &[
+        (
+            "../zqx-out",
+            "`../zqx-out` is not a valid preset name, it can't be a path.",
+        ),
+        (
+            "zqx/nested",
+            "`zqx/nested` is not a valid preset name, it can't be a path.",
+        ),
+        ("..", "`..` is not a valid preset name, it can't be a path."),
+        (
+            "List.yaml",
+            "`List` is the name of a preset subcommand, the preset could only be applied with \
+            `twitchctl preset -- List`. Choose another name.",
+        ),
+    ] {
+        assert_eq!(
+            run(&["mv", "zqx-base", name])
+                .await
+                .unwrap_err()
+                .to_string(),
+            *error
+        );
+        assert_eq!(run(&["new", name]).await.unwrap_err().to_string(), *error);
+    }
+
+    // with a terminal, the user is asked to confirm instead
+    if !atty::is(atty::Stream::Stdin) {
+        assert_eq!(
+            run(&["rm", "zqx-tw"]).await.unwrap_err().to_string(),
+            "`zqx-tw` only matches `zqx-two.yaml` fuzzily, name the preset exactly to remove it."
+        );
+        assert!(presets.join("zqx-two.yaml").exists());
+    }
+    run(&["rm", "zqx-two"]).await.unwrap();
+    assert!(!presets.join("zqx-two.yaml").exists());
+    assert_eq!(
+        run(&["rm", "zqx-tw"]).await.unwrap_err().to_string(),
+        "No matching presets found."
+    );
+}
+
+#[tokio::test]
+async fn new_presets_start_from_the_channel() {
+    let presets = presets();
+    let twitch = FakeTwitch::new(State::seeded());
+
+    preset(
+        Some(twitch.client().await),
+        command(&[
+            "new",
+            "channel.json",
+            "--from-channel",
+            "--title",
+            "Override",
+        ]),
+    )
+    .await
+    .unwrap();
+
+    let config = read_config(&presets.join("channel.json")).unwrap();
+    assert_eq!(config.title.as_deref(), Some("Override"));
+    assert_eq!(config.category.as_deref(), Some("Just Chatting"));
+    assert_eq!(config.tags, Some(vec!["Chill".to_string()]));
+    assert_eq!(
+        config.rewards,
+        Some(vec![title("Song request"), title("Stretch")])
+    );
+    assert!(twitch.state().requests.iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn edited_presets_are_only_saved_when_valid() {
+    let presets = presets();
+    let file = presets.join("edited.toml");
+    fs::write(&file, "title = \"Before\"\n").unwrap();
+    // the only test of this file using the editor
+    env::remove_var("VISUAL");
+
+    env::set_var("EDITOR", "sed -i s/Before/After/");
+    preset(None, command(&["edit", "edited"])).await.unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "title = \"After\"\n");
+
+    env::set_var("EDITOR", "false");
+    let error = preset(None, command(&["edit", "edited"]))
+        .await
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Could not run the editor `false`"));
+
+    // with a terminal, the user is asked to edit it again instead
+    if !atty::is(atty::Stream::Stdin) {
+        env::set_var("EDITOR", "sed -i s/title/=/");
+        let error = preset(None, command(&["edit", "edited"]))
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The preset was not saved, Failed to parse configuration"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "title = \"After\"\n");
+
+        // the draft is checked as the preset it replaces
+        env::set_var("EDITOR", "sed -i 1iextends=[\"edited\"]");
+        let error = preset(None, command(&["edit", "edited"]))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().starts_with(
+            "The preset was not saved, The presets extend each other in a cycle: \
+            edited.toml -> edited.toml"
+        ));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "title = \"After\"\n");
+    }
+}
+
+#[test]
+fn keys_values_sections_and_comments_are_highlighted() {
+    assert_eq!(
+        highlight("# rewards\ntitle = \"Live\"\n[rewards]\ncost = 5", "toml"),
+        [
+            Style::new().dimmed().paint("# rewards").to_string(),
+            format!("{}{}", Blue.paint("title ="), Green.paint(" \"Live\"")),
+            Yellow.bold().paint("[rewards]").to_string(),
+            format!("{}{}", Blue.paint("cost ="), Purple.paint(" 5")),
+        ]
+        .join("\n")
+    );
+    assert_eq!(
+        highlight(
+            "{\n  \"title\": \"Live\",\n  \"tags\": [\n    \"Chill\"\n  ]\n}",
+            "json"
+        ),
+        [
+            "{".to_string(),
+            format!(
+                "{}{}",
+                Blue.paint("  \"title\":"),
+                Green.paint(" \"Live\",")
+            ),
+            format!("{} [", Blue.paint("  \"tags\":")),
+            Green.paint("    \"Chill\"").to_string(),
+            "  ]".to_string(),
+            "}".to_string(),
+        ]
+        .join("\n")
+    );
+}
@@ -0,0 +1,142 @@
+//! Checks that requests are spread over the rate limit and retried when Twitch fails
+
+mod common;
+
+use common::{Bucket, FakeTwitch, State};
+use futures::future::join_all;
+use std::{fs, time::Duration};
+use structopt::StructOpt;
+use twitch_api2::{helix::points::UpdateCustomRewardBody, types::TagId};
+use twitchctl::{
+    file::handle_file,
+    http::MAX_IN_FLIGHT,
+    rewards::{rewards, RewardsSubcommand},
+};
+
+const REWARDS_FILE: &str = "rewards = [\"hydrate\", \"stretch\"]\n";
+
+#[tokio::test]
+async fn requests_wait_for_the_bucket_to_refill() {
+    let twitch = FakeTwitch::new(State {
+        bucket: Some(Bucket::new(3)),
+        ..State::seeded()
+    });
+    let file = common::test_dir("bucket").join("stream.toml");
+    fs::write(&file, REWARDS_FILE).unwrap();
+
+    handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert!(state.reward("Hydrate").is_enabled);
+    assert!(state.reward("Stretch").is_enabled);
+    assert!(!state.reward("Song request").is_enabled);
+}
+
+#[tokio::test]
+async fn rate_limited_and_failed_requests_are_retried() {
+    let twitch = FakeTwitch::new(State {
+        failures: vec![
+            ("PATCH /helix/channels", 429),
+            ("PATCH /helix/channels", 503),
+        ],
+        ..State::seeded()
+    });
+    let file = common::test_dir("retry").join("stream.toml");
+    fs::write(&file, "title = \"Third time's the charm\"\n").unwrap();
+
+    handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap();
+
+    let state = twitch.state();
+    assert_eq!(state.channel().title, "Third time's the charm");
+    assert_eq!(
+        state
+            .requests
+            .iter()
+            .filter(|r| *r == "PATCH /helix/channels")
+            .count(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn posts_are_not_retried_when_twitch_failed() {
+    let twitch = FakeTwitch::new(State {
+        failures: vec![("POST /helix/channel_points/custom_rewards", 500)],
+        ..State::seeded()
+    });
+    let command = RewardsSubcommand::from_iter(&["rewards", "add", "-t", "Dance", "-c", "10"]);
+
+    let error = rewards(twitch.client().await, command).await.unwrap_err();
+
+    assert!(error
+        .to_string()
+        .starts_with("Twitch failed with status 500"));
+    assert_eq!(
+        twitch
+            .state()
+            .requests
+            .iter()
+            .filter(|r| r.starts_with("POST"))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn requests_failing_after_all_retries_are_reported() {
+    let twitch = FakeTwitch::new(State {
+        failures: vec![("id=r-song", 503); 4],
+        ..State::seeded()
+    });
+    let file = common::test_dir("reported").join("stream.toml");
+    fs::write(&file, REWARDS_FILE).unwrap();
+
+    let error = handle_file(twitch.client().await, &file, true, &[])
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Could not apply the config, disabling reward `Song request` failed: \
+        Twitch failed with status 503: the fake failed as told\n\
+        Restored the changes already made:\n  \
+        enabling reward `Hydrate`"
+    );
+    let state = twitch.state();
+    assert!(!state.reward("Hydrate").is_enabled);
+    assert!(state.reward("Stretch").is_enabled);
+    assert!(state.reward("Song request").is_enabled);
+}
+
+#[tokio::test]
+async fn no_more_than_the_allowed_requests_are_in_flight() {
+    let twitch = FakeTwitch::new(State {
+        latency: Some(Duration::from_millis(20)),
+        ..State::seeded()
+    });
+    let client = twitch.client().await;
+    let user = client.get_user_id().unwrap().clone();
+    let rewards = client.get_rewards(&user).await.unwrap();
+    let updates = rewards
+        .iter()
+        .cycle()
+        .take(5 * MAX_IN_FLIGHT)
+        .map(|reward| {
+            let body = UpdateCustomRewardBody::builder().is_enabled(true).build();
+            client.update_custom_reward(&user, &reward.id, body)
+        });
+    let tags: Vec<TagId> = vec!["t-chill".to_string().into()];
+
+    let (tags, updates) = tokio::join!(client.replace_stream_tags(&user, tags), join_all(updates));
+
+    tags.unwrap();
+    assert!(updates.into_iter().all(|update| update.is_ok()));
+    let state = twitch.state();
+    assert_eq!(state.peak_in_flight, MAX_IN_FLIGHT);
+    assert_eq!(state.in_flight, 0);
+    assert_eq!(state.stream_tag_names(), ["English", "Chill"]);
+}
@@ -0,0 +1,82 @@
+//! Saves the channel of the fake Twitch as config file and applies it again
+
+mod common;
+
+use common::{FakeTwitch, State, Tag, USER_ID};
+use std::fs;
+use twitchctl::{
+    file::{load_config, write_config},
+    plan::Plan,
+    snapshot::snapshot,
+};
+
+#[tokio::test]
+async fn snapshot_is_written_in_the_config_schema() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let file = common::test_dir("snapshot").join("preset.toml");
+    let client = twitch.client().await;
+
+    let config = snapshot(&client, None).await.unwrap();
+    write_config(&config, &file).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        r#"title = 'Old title'
+category = 'Just Chatting'
+language = 'en'
+config_locale = 'en-us'
+tags = ['Chill']
+rewards = [
+    'Song request',
+    'Stretch',
+]
+"#
+    );
+    assert!(twitch.state().requests.iter().all(|r| r.starts_with("GET")));
+}
+
+#[tokio::test]
+async fn snapshots_in_every_format_apply_without_changes() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let dir = common::test_dir("snapshot-formats");
+    let client = twitch.client().await;
+    let config = snapshot(&client, None).await.unwrap();
+
+    for name in &[
+        "preset.toml",
+        "preset.ini",
+        "preset.yml",
+        "preset.yaml",
+        "preset.json",
+    ] {
+        let file = dir.join(name);
+        write_config(&config, &file).unwrap();
+
+        let plan = Plan::new(&client, &load_config(&file, true).unwrap())
+            .await
+            .unwrap();
+        assert!(plan.is_empty(), "{}:\n{}", name, plan.render(false));
+    }
+}
+
+#[tokio::test]
+async fn tags_without_a_name_are_left_out() {
+    let mut state = State::seeded();
+    state.tags.push(Tag {
+        id: "t-nameless".to_string(),
+        name: String::new(),
+        is_auto: false,
+    });
+    state
+        .stream_tags
+        .get_mut(USER_ID)
+        .unwrap()
+        .push("t-nameless".to_string());
+    let twitch = FakeTwitch::new(state);
+
+    let config = snapshot(&twitch.client().await, Some("de-de".to_string()))
+        .await
+        .unwrap();
+
+    assert_eq!(config.tags, Some(vec!["Chill".to_string()]));
+}
@@ -0,0 +1,90 @@
+//! Checks that the tag catalogue is cached on disk and used when Twitch can't be reached
+
+mod common;
+
+use common::{FakeTwitch, State};
+use std::time::Duration;
+use structopt::StructOpt;
+use twitchctl::{
+    cache::TagCache,
+    tags::{tags, TagsSubcommand},
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn catalogue_requests(twitch: &FakeTwitch) -> usize {
+    twitch
+        .state()
+        .requests
+        .iter()
+        .filter(|r| *r == "GET /helix/tags/streams")
+        .count()
+}
+
+#[tokio::test]
+async fn tags_are_fetched_once_while_the_cache_is_fresh() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let cache = TagCache::in_dir(common::test_dir("cache-fresh"), DAY, false);
+
+    for tag in &["programming", "speedrun"] {
+        let client = twitch.client().await.with_tag_cache(Some(cache.clone()));
+        let command = TagsSubcommand::from_iter(&["tags", "set", "--add", tag]);
+        tags(client, "en-us", command).await.unwrap();
+    }
+
+    // the fake serves the 5 tags in pages of 2
+    assert_eq!(catalogue_requests(&twitch), 3);
+    assert_eq!(
+        twitch.state().stream_tag_names(),
+        ["English", "Chill", "Programming", "Speedrun"]
+    );
+}
+
+#[tokio::test]
+async fn expired_or_refreshed_caches_are_fetched_again() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let dir = common::test_dir("cache-refresh");
+
+    for cache in &[
+        TagCache::in_dir(&dir, DAY, false),
+        TagCache::in_dir(&dir, DAY, true),
+        TagCache::in_dir(&dir, Duration::from_secs(0), false),
+    ] {
+        let client = twitch.client().await.with_tag_cache(Some(cache.clone()));
+        assert_eq!(client.get_all_tags().await.unwrap().len(), 5);
+    }
+
+    assert_eq!(catalogue_requests(&twitch), 9);
+}
+
+#[tokio::test]
+async fn cached_tags_are_used_when_twitch_is_unreachable() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let cache = TagCache::in_dir(
+        common::test_dir("cache-offline"),
+        Duration::from_secs(0),
+        false,
+    );
+    let client = twitch.client().await.with_tag_cache(Some(cache.clone()));
+    client.get_all_tags().await.unwrap();
+
+    twitch.state().unreachable = true;
+    let ids = client
+        .get_tag_ids_matching(&["chill".to_string()], "en-us")
+        .await
+        .unwrap();
+
+    assert_eq!(ids, ["t-chill"]);
+}
+
+#[tokio::test]
+async fn without_cached_tags_unreachable_twitch_fails() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let cache = TagCache::in_dir(common::test_dir("cache-empty"), DAY, false);
+    let client = twitch.client().await.with_tag_cache(Some(cache));
+
+    twitch.state().unreachable = true;
+    let error = client.get_all_tags().await.unwrap_err();
+
+    assert!(error.to_string().starts_with("Could not reach Twitch"));
+}
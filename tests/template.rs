@@ -0,0 +1,122 @@
+//! Renders templated titles and applies them to the fake Twitch
+
+mod common;
+
+use chrono::Local;
+use common::{FakeTwitch, State};
+use std::{env, fs};
+use structopt::StructOpt;
+use twitchctl::{
+    file::Config,
+    plan::Plan,
+    template::{render, Counters, TemplateOptions, Values},
+};
+
+fn config(title: &str) -> Config {
+    Config {
+        title: Some(title.to_string()),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn values_counters_dates_and_env_are_filled_in() {
+    let counters = Counters::in_dir(common::test_dir("render"));
+    fs::write(counters.file(), "devlog = 41\n").unwrap();
+    env::set_var("TWITCHCTL_TEST_GAME", "Celeste");
+    let values =
+        Values::new(vec![("guest".to_string(), "Ferris".to_string())]).with_counters(counters);
+
+    let rendered = render(
+        "Day {{counter.devlog}}, {{ counter.new }} and {{counter.devlog}}: {{env.TWITCHCTL_TEST_GAME}} with {{guest}} ({{date:%Y}}, {{date}})",
+        &values,
+    )
+    .unwrap();
+
+    assert_eq!(
+        rendered.text,
+        format!(
+            "Day 42, 1 and 42: Celeste with Ferris ({}, {})",
+            Local::now().format("%Y"),
+            Local::now().format("%Y-%m-%d")
+        )
+    );
+    assert_eq!(rendered.counters, ["devlog", "new"]);
+    assert_eq!(render("No {braces}", &values).unwrap().text, "No {braces}");
+
+    let error = |template: &str| render(template, &values).unwrap_err().to_string();
+    assert_eq!(
+        error("Hi {{host}}"),
+        "Unknown template value `host`, give it with `--set host=...`."
+    );
+    assert_eq!(
+        error("Day {{counter.devlog"),
+        "The `{{` at character 5 of `Day {{counter.devlog` is not closed."
+    );
+    assert_eq!(
+        error("{{env.TWITCHCTL_TEST_UNSET}}"),
+        "The environment variable `TWITCHCTL_TEST_UNSET` used in the title is not set."
+    );
+    assert_eq!(error("{{date:%Q}}"), "Invalid date format `%Q`.");
+}
+
+#[test]
+fn set_takes_key_value_pairs() {
+    let options = TemplateOptions::from_iter(&["set", "--set", "a=b=c", "--set", "empty="]);
+    assert_eq!(
+        options.set,
+        [
+            ("a".to_string(), "b=c".to_string()),
+            ("empty".to_string(), String::new())
+        ]
+    );
+    assert!(TemplateOptions::from_iter_safe(&["set", "--set", "novalue"]).is_err());
+}
+
+#[tokio::test]
+async fn counters_count_up_only_when_applied() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let counters = Counters::in_dir(common::test_dir("counters"));
+    let values = Values::new(vec![]).with_counters(counters.clone());
+    let config = config("Devlog {{counter.devlog}}");
+    let client = twitch.client().await;
+
+    let plan = Plan::with_values(&client, &config, &values).await.unwrap();
+    assert_eq!(plan.render(false), "~ title: \"Old title\" -> \"Devlog 1\"");
+    assert!(!counters.file().exists());
+
+    let dry_run = twitch.client().await.with_dry_run(true);
+    let plan = Plan::with_values(&dry_run, &config, &values).await.unwrap();
+    plan.apply(&dry_run).await.unwrap();
+    assert!(!counters.file().exists());
+
+    plan.apply(&client).await.unwrap();
+    assert_eq!(twitch.state().channel().title, "Devlog 1");
+    assert_eq!(counters.load().unwrap()["devlog"], 1);
+
+    Plan::with_values(&client, &config, &values)
+        .await
+        .unwrap()
+        .apply(&client)
+        .await
+        .unwrap();
+    assert_eq!(twitch.state().channel().title, "Devlog 2");
+    assert_eq!(counters.load().unwrap()["devlog"], 2);
+}
+
+#[tokio::test]
+async fn rendered_title_is_checked_before_any_request() {
+    let twitch = FakeTwitch::new(State::seeded());
+    let client = twitch.client().await;
+    let values = Values::new(vec![("long".to_string(), "a".repeat(138))]);
+    let sent = twitch.state().requests.len();
+
+    let error = Plan::with_values(&client, &config("{{long}} #1"), &values)
+        .await
+        .unwrap_err();
+
+    assert!(error
+        .to_string()
+        .ends_with("has 141 characters, Twitch allows at most 140."));
+    assert_eq!(twitch.state().requests.len(), sent);
+}